@@ -0,0 +1,291 @@
+// semantic actions, small expressions attached to an alternative as {...}
+//
+// An action computes the attribute of a rule expansion from the attributes of the
+// symbols of its alternative: $1, $2, .. by position, or $name for a symbol bound
+// with "as name". A symbol without an action of its own has its text as attribute.
+//
+//   <sum>::=<num as a>"+"<sum as b>{$a+$b}|<num>{$1}
+//
+// Values are integers or strings; arithmetic parses strings as integers.
+//   + - * / %     on integers, / and % truncate
+//   'text'        a string literal, "text" too
+//   len(s)        length of s in bytes
+//   cat(a, ..)    concatenation
+//   int(s) str(x) conversions
+//   sum(s) xor(s) sum and xor of the bytes of s, for checksums
+//   hex(n)        n in lowercase hexadecimal
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Int(i64),
+    Str(String),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Str(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl Value {
+    pub fn int(&self) -> Result<i64, String> {
+        match self {
+            Value::Int(n) => Ok(*n),
+            Value::Str(s) => s
+                .trim()
+                .parse()
+                .map_err(|_| format!("{:?} is not an integer", s)),
+        }
+    }
+}
+
+// a reference to a symbol of the alternative
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Slot {
+    Pos(usize), // $1 is the first symbol
+    Name(String),
+}
+
+enum Expr {
+    Lit(Value),
+    Var(Slot),
+    Neg(Box<Expr>),
+    Op(char, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+pub struct Action {
+    pub source: String,
+    expr: Expr,
+}
+
+const FUNCTIONS: [&str; 7] = ["len", "cat", "int", "str", "sum", "xor", "hex"];
+
+// recursive descent over the chars of the source
+struct Parser<'a> {
+    s: &'a [char],
+    i: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&mut self) -> Option<char> {
+        while self.s.get(self.i).is_some_and(|c| c.is_whitespace()) {
+            self.i += 1;
+        }
+        self.s.get(self.i).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.i += 1;
+        }
+        found
+    }
+
+    fn word(&mut self) -> String {
+        let start = self.i;
+        while self
+            .s
+            .get(self.i)
+            .is_some_and(|c| c.is_ascii_alphanumeric() || *c == '_')
+        {
+            self.i += 1;
+        }
+        self.s[start..self.i].iter().collect()
+    }
+
+    // sum := product (("+"|"-") product)*
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut left = self.product()?;
+        while let Some(op @ ('+' | '-')) = self.peek() {
+            self.i += 1;
+            left = Expr::Op(op, Box::new(left), Box::new(self.product()?));
+        }
+        Ok(left)
+    }
+
+    // product := unary (("*"|"/"|"%") unary)*
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut left = self.unary()?;
+        while let Some(op @ ('*' | '/' | '%')) = self.peek() {
+            self.i += 1;
+            left = Expr::Op(op, Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.eat('-') {
+            true => Ok(Expr::Neg(Box::new(self.unary()?))),
+            false => self.atom(),
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some('(') => {
+                self.i += 1;
+                let e = self.sum()?;
+                match self.eat(')') {
+                    true => Ok(e),
+                    false => Err("expect )".to_string()),
+                }
+            }
+            Some('$') => {
+                self.i += 1;
+                let w = self.word();
+                match w.parse::<usize>() {
+                    Ok(0) => Err("$0 is no symbol, the first is $1".to_string()),
+                    Ok(n) => Ok(Expr::Var(Slot::Pos(n))),
+                    Err(_) if w.is_empty() => Err("expect a variable after $".to_string()),
+                    Err(_) => Ok(Expr::Var(Slot::Name(w))),
+                }
+            }
+            Some(q @ ('\'' | '"')) => {
+                self.i += 1;
+                let start = self.i;
+                while self.s.get(self.i).is_some_and(|c| *c != q) {
+                    self.i += 1;
+                }
+                if self.i == self.s.len() {
+                    return Err("unclosed string".to_string());
+                }
+                self.i += 1;
+                let s = self.s[start..self.i - 1].iter().collect();
+                Ok(Expr::Lit(Value::Str(s)))
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let w = self.word();
+                let n = w.parse().map_err(|_| format!("bad number {}", w))?;
+                Ok(Expr::Lit(Value::Int(n)))
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                let name = self.word();
+                if !FUNCTIONS.contains(&name.as_str()) {
+                    return Err(format!("unknown function {}", name));
+                }
+                if !self.eat('(') {
+                    return Err(format!("expect ( after {}", name));
+                }
+                let mut args = vec![];
+                if !self.eat(')') {
+                    loop {
+                        args.push(self.sum()?);
+                        if self.eat(')') {
+                            break;
+                        }
+                        if !self.eat(',') {
+                            return Err(format!("expect , or ) in {}(...)", name));
+                        }
+                    }
+                }
+                let arity = match name.as_str() {
+                    "cat" => args.len(),
+                    _ => 1,
+                };
+                if args.len() != arity {
+                    return Err(format!("{} takes one argument", name));
+                }
+                Ok(Expr::Call(name, args))
+            }
+            Some(c) => Err(format!("unexpected {}", c)),
+            None => Err("unexpected end".to_string()),
+        }
+    }
+}
+
+impl Action {
+    pub fn new(source: &str) -> Result<Self, String> {
+        let chars: Vec<char> = source.chars().collect();
+        let mut p = Parser { s: &chars, i: 0 };
+        let expr = p
+            .sum()
+            .and_then(|e| match p.peek() {
+                None => Ok(e),
+                Some(c) => Err(format!("unexpected {}", c)),
+            })
+            .map_err(|e| format!("action {{{}}}: {}", source, e))?;
+        Ok(Action {
+            source: source.to_string(),
+            expr,
+        })
+    }
+
+    // the symbols the action refers to
+    pub fn slots(&self) -> Vec<&Slot> {
+        let mut slots = vec![];
+        let mut todo = vec![&self.expr];
+        while let Some(e) = todo.pop() {
+            match e {
+                Expr::Lit(_) => (),
+                Expr::Var(slot) => slots.push(slot),
+                Expr::Neg(e) => todo.push(e),
+                Expr::Op(_, l, r) => todo.extend([&**l, &**r]),
+                Expr::Call(_, args) => todo.extend(args),
+            }
+        }
+        slots
+    }
+
+    // args are the attributes of the symbols, names maps a variable to its position
+    pub fn eval(&self, args: &[Value], names: &HashMap<&str, usize>) -> Result<Value, String> {
+        eval(&self.expr, args, names).map_err(|e| format!("action {{{}}}: {}", self.source, e))
+    }
+}
+
+fn eval(e: &Expr, args: &[Value], names: &HashMap<&str, usize>) -> Result<Value, String> {
+    let slot = |n: usize| args.get(n).ok_or_else(|| format!("no symbol ${}", n + 1));
+    let bytes = |v: Value| v.to_string().into_bytes();
+    Ok(match e {
+        Expr::Lit(v) => v.clone(),
+        Expr::Var(Slot::Pos(n)) => slot(n - 1)?.clone(),
+        Expr::Var(Slot::Name(name)) => {
+            let n = names
+                .get(name.as_str())
+                .ok_or(format!("unbound ${}", name))?;
+            slot(*n)?.clone()
+        }
+        Expr::Neg(e) => Value::Int(
+            eval(e, args, names)?
+                .int()?
+                .checked_neg()
+                .ok_or("overflow")?,
+        ),
+        Expr::Op(op, l, r) => {
+            let (l, r) = (eval(l, args, names)?.int()?, eval(r, args, names)?.int()?);
+            let n = match op {
+                '+' => l.checked_add(r),
+                '-' => l.checked_sub(r),
+                '*' => l.checked_mul(r),
+                _ if r == 0 => return Err("division by zero".to_string()),
+                '/' => l.checked_div(r),
+                _ => l.checked_rem(r),
+            };
+            Value::Int(n.ok_or("overflow")?)
+        }
+        Expr::Call(name, args_) => {
+            let mut vals = args_
+                .iter()
+                .map(|a| eval(a, args, names))
+                .collect::<Result<Vec<_>, _>>()?;
+            let v = match name.as_str() {
+                "cat" => return Ok(Value::Str(vals.iter().map(|v| v.to_string()).collect())),
+                _ => vals.remove(0),
+            };
+            match name.as_str() {
+                "len" => Value::Int(v.to_string().len() as i64),
+                "int" => Value::Int(v.int()?),
+                "str" => Value::Str(v.to_string()),
+                "sum" => Value::Int(bytes(v).iter().map(|b| *b as i64).sum()),
+                "xor" => Value::Int(bytes(v).iter().fold(0, |x, b| x ^ *b as i64)),
+                _ => Value::Str(format!("{:x}", v.int()?)),
+            }
+        }
+    })
+}
@@ -0,0 +1,275 @@
+// minimum arbitrary precision unsigned integer
+// only what counting derivations needs: + - * divrem, compare, print and random below a bound
+use rand::Rng;
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, AddAssign, Mul, Sub};
+use std::str::FromStr;
+
+// little endian base 2^32 digits, no trailing zero digits, zero is the empty vec
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct BigUint {
+    d: Vec<u32>,
+}
+
+impl BigUint {
+    pub fn zero() -> Self {
+        Self { d: vec![] }
+    }
+
+    pub fn one() -> Self {
+        Self::from(1u64)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.d.is_empty()
+    }
+
+    fn trim(mut self) -> Self {
+        while self.d.last() == Some(&0) {
+            self.d.pop();
+        }
+        self
+    }
+
+    pub fn bits(&self) -> usize {
+        match self.d.last() {
+            None => 0,
+            Some(top) => self.d.len() * 32 - top.leading_zeros() as usize,
+        }
+    }
+
+    fn bit(&self, i: usize) -> bool {
+        self.d.get(i / 32).is_some_and(|x| x >> (i % 32) & 1 == 1)
+    }
+
+    fn shl1_or(&mut self, bit: bool) {
+        let mut carry = bit as u32;
+        for x in self.d.iter_mut() {
+            let next = *x >> 31;
+            *x = *x << 1 | carry;
+            carry = next;
+        }
+        if carry != 0 {
+            self.d.push(carry);
+        }
+    }
+
+    // (self / rhs, self % rhs), rhs must not be zero
+    pub fn div_rem(&self, rhs: &BigUint) -> (BigUint, BigUint) {
+        assert!(!rhs.is_zero(), "division by zero");
+        let mut q = BigUint {
+            d: vec![0; self.d.len()],
+        };
+        let mut r = BigUint::zero();
+        for i in (0..self.bits()).rev() {
+            r.shl1_or(self.bit(i));
+            if r >= *rhs {
+                r = &r - rhs;
+                q.d[i / 32] |= 1 << (i % 32);
+            }
+        }
+        (q.trim(), r)
+    }
+
+    fn div_rem_small(&self, rhs: u32) -> (BigUint, u32) {
+        let mut q = vec![0; self.d.len()];
+        let mut r = 0u64;
+        for i in (0..self.d.len()).rev() {
+            let cur = r << 32 | self.d[i] as u64;
+            q[i] = (cur / rhs as u64) as u32;
+            r = cur % rhs as u64;
+        }
+        (BigUint { d: q }.trim(), r as u32)
+    }
+
+    // uniform in [0, bound), bound must not be zero
+    pub fn random_below<R: Rng + ?Sized>(bound: &BigUint, rng: &mut R) -> BigUint {
+        assert!(!bound.is_zero(), "empty range");
+        let bits = bound.bits();
+        loop {
+            let mut d: Vec<u32> = (0..bound.d.len()).map(|_| rng.gen()).collect();
+            if !bits.is_multiple_of(32) {
+                *d.last_mut().unwrap() &= (1 << (bits % 32)) - 1;
+            }
+            let x = BigUint { d }.trim();
+            if x < *bound {
+                return x;
+            }
+        }
+    }
+
+    // nearest f64, saturating to infinity
+    pub fn to_f64(&self) -> f64 {
+        self.d
+            .iter()
+            .rev()
+            .fold(0.0, |acc, &x| acc * 4294967296.0 + x as f64)
+    }
+
+    pub fn to_u64(&self) -> Option<u64> {
+        match self.d.len() {
+            0 => Some(0),
+            1 => Some(self.d[0] as u64),
+            2 => Some(self.d[0] as u64 | (self.d[1] as u64) << 32),
+            _ => None,
+        }
+    }
+}
+
+impl From<u64> for BigUint {
+    fn from(x: u64) -> Self {
+        BigUint {
+            d: vec![x as u32, (x >> 32) as u32],
+        }
+        .trim()
+    }
+}
+
+impl Ord for BigUint {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.d
+            .len()
+            .cmp(&other.d.len())
+            .then_with(|| self.d.iter().rev().cmp(other.d.iter().rev()))
+    }
+}
+
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Add for &BigUint {
+    type Output = BigUint;
+    fn add(self, rhs: &BigUint) -> BigUint {
+        let mut d = Vec::with_capacity(self.d.len().max(rhs.d.len()) + 1);
+        let mut carry = 0u64;
+        for i in 0..self.d.len().max(rhs.d.len()) {
+            let s =
+                *self.d.get(i).unwrap_or(&0) as u64 + *rhs.d.get(i).unwrap_or(&0) as u64 + carry;
+            d.push(s as u32);
+            carry = s >> 32;
+        }
+        d.push(carry as u32);
+        BigUint { d }.trim()
+    }
+}
+
+impl AddAssign<&BigUint> for BigUint {
+    fn add_assign(&mut self, rhs: &BigUint) {
+        *self = &*self + rhs;
+    }
+}
+
+// panics when rhs > self
+impl Sub for &BigUint {
+    type Output = BigUint;
+    fn sub(self, rhs: &BigUint) -> BigUint {
+        assert!(*self >= *rhs, "subtraction underflow");
+        let mut d = Vec::with_capacity(self.d.len());
+        let mut borrow = 0i64;
+        for i in 0..self.d.len() {
+            let mut s = self.d[i] as i64 - *rhs.d.get(i).unwrap_or(&0) as i64 - borrow;
+            borrow = (s < 0) as i64;
+            if s < 0 {
+                s += 1 << 32;
+            }
+            d.push(s as u32);
+        }
+        BigUint { d }.trim()
+    }
+}
+
+impl Mul for &BigUint {
+    type Output = BigUint;
+    fn mul(self, rhs: &BigUint) -> BigUint {
+        if self.is_zero() || rhs.is_zero() {
+            return BigUint::zero();
+        }
+        let mut d = vec![0u32; self.d.len() + rhs.d.len()];
+        for (i, &a) in self.d.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &b) in rhs.d.iter().enumerate() {
+                let cur = d[i + j] as u64 + a as u64 * b as u64 + carry;
+                d[i + j] = cur as u32;
+                carry = cur >> 32;
+            }
+            d[i + rhs.d.len()] = carry as u32;
+        }
+        BigUint { d }.trim()
+    }
+}
+
+impl fmt::Display for BigUint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        let mut digits = vec![];
+        let mut x = self.clone();
+        while !x.is_zero() {
+            let (q, r) = x.div_rem_small(1_000_000_000);
+            digits.push(r);
+            x = q;
+        }
+        write!(f, "{}", digits.pop().unwrap())?;
+        for chunk in digits.iter().rev() {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for BigUint {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        if s.is_empty() || !s.bytes().all(|c| c.is_ascii_digit()) {
+            return Err(format!("{} is not a number", s));
+        }
+        let ten = BigUint::from(10u64);
+        Ok(s.bytes().fold(BigUint::zero(), |acc, c| {
+            &(&acc * &ten) + &BigUint::from((c - b'0') as u64)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(s: &str) -> BigUint {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn print_parse() {
+        for s in ["0", "7", "4294967296", "123456789012345678901234567890"] {
+            assert_eq!(big(s).to_string(), s);
+        }
+    }
+
+    #[test]
+    fn arith() {
+        let a = big("123456789012345678901234567890");
+        let b = big("987654321098765432");
+        assert_eq!(
+            (&a * &b).to_string(),
+            "121932631137021795212620027521140070120989178480"
+        );
+        assert_eq!(&(&a + &b) - &b, a);
+        let (q, r) = a.div_rem(&b);
+        assert_eq!(&(&q * &b) + &r, a);
+        assert!(r < b);
+    }
+
+    #[test]
+    fn random_below_bound() {
+        let mut rng = rand::thread_rng();
+        let bound = big("100000000000000000000");
+        for _ in 0..100 {
+            assert!(BigUint::random_below(&bound, &mut rng) < bound);
+        }
+    }
+}
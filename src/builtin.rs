@@ -0,0 +1,458 @@
+// built-in value generators, written <@name(args)> in mBNF
+//
+//   <@int(lo,hi)>                 integer in lo..=hi
+//   <@float(lo,hi[,digits])>      number in lo..hi with digits decimals, 6 by default
+//   <@uuid>                       random (version 4) uuid
+//   <@date(from,to[,format])>     day in from..=to, YYYY-MM-DD, format takes %Y %y %m %d %%
+//   <@hex(n)>                     n lowercase hex digits
+//   <@int(dist=zipf,n=1000)>      keyword form, numbers from a distribution, see distribution
+//   <@float(dist=normal,...)>
+//   <@pick(file="names.txt")>     a line of a word list, see wordlist
+//   <@pick(dict=cities)>          an entry of a bundled dictionary
+//
+// Generation samples the value directly. Everything that works on the rules
+// (recognizer, counts, trees) sees the shape of the values instead, a regex that
+// accepts every value the generator can produce, or for picks the list itself and
+// for hex strings runs of digits.
+use crate::collection::Rules;
+use crate::distribution::Numbers;
+use crate::parser::Symbol;
+use crate::regex::{escape, Regex};
+use crate::wordlist::{ListSource, Lists, BUNDLED};
+use rand::{Rng, RngCore};
+
+enum Kind {
+    Int { lo: i64, hi: i64 },
+    Float { lo: f64, hi: f64, digits: usize },
+    Uuid,
+    Date { from: i64, to: i64, format: String }, // days since 1970-01-01
+    Hex { len: usize },
+    Pick(ListSource),
+    Numbers(Numbers),
+}
+
+pub struct Builtin {
+    pub source: String, // between <@ and >, as written
+    kind: Kind,
+}
+
+// arguments split at top level commas, (), <> and quotes nest
+pub fn split_args(args: &str) -> Vec<String> {
+    let (mut parts, mut cur) = (vec![], String::new());
+    let (mut depth, mut quoted) = (0i32, false);
+    for c in args.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            '(' | '<' if !quoted => depth += 1,
+            ')' | '>' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                parts.push(cur.trim().to_string());
+                cur.clear();
+                continue;
+            }
+            _ => (),
+        }
+        cur.push(c);
+    }
+    if !cur.trim().is_empty() || !parts.is_empty() {
+        parts.push(cur.trim().to_string());
+    }
+    parts
+}
+
+fn number<T: std::str::FromStr>(s: &str) -> Result<T, String> {
+    s.parse().map_err(|_| format!("{} is not a number", s))
+}
+
+// days since 1970-01-01 of a proleptic gregorian date
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(z: i64) -> (i64, i64, i64) {
+    let z = z + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + (m <= 2) as i64, m, d)
+}
+
+fn date(s: &str) -> Result<i64, String> {
+    let bad = || format!("{} is not a date YYYY-MM-DD", s);
+    let parts: Vec<i64> = s
+        .split('-')
+        .map(|p| p.parse().map_err(|_| bad()))
+        .collect::<Result<_, _>>()?;
+    match parts[..] {
+        [y, m, d] if (0..=9999).contains(&y) && (1..=12).contains(&m) && d >= 1 => {
+            let days = days_from_civil(y, m, d);
+            match civil_from_days(days) == (y, m, d) {
+                true => Ok(days),
+                false => Err(bad()),
+            }
+        }
+        _ => Err(bad()),
+    }
+}
+
+// key=value, the value unquoted
+pub(crate) fn keyword(arg: &str) -> Option<(&str, &str)> {
+    let (k, v) = arg.split_once('=')?;
+    let v = v.trim();
+    Some((
+        k.trim(),
+        v.strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(v),
+    ))
+}
+
+fn digits(n: u64) -> usize {
+    n.to_string().len()
+}
+
+// the numbers with d decimals in lo..hi as the integers first..end over a scale of
+// 10^d, so a sample is one of them and rounding never reaches hi
+fn grid(lo: f64, hi: f64, d: usize) -> (f64, f64, f64) {
+    let scale = 10f64.powi(d.min(400) as i32);
+    (scale, (lo * scale).ceil(), (hi * scale).ceil())
+}
+
+impl Builtin {
+    pub fn new(source: &str) -> Result<Self, String> {
+        let (name, args) = match source.split_once('(') {
+            Some((name, rest)) => match rest.strip_suffix(')') {
+                Some(args) => (name, split_args(args)),
+                None => return Err(format!("unclosed arguments in <@{}>", source)),
+            },
+            None => (source, vec![]),
+        };
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let kind = match (name, &args[..]) {
+            ("int" | "float", [first, ..]) if keyword(first).is_some() => {
+                Kind::Numbers(Numbers::parse(name == "int", &args)?)
+            }
+            ("int", [lo, hi]) => Kind::Int {
+                lo: number(lo)?,
+                hi: number(hi)?,
+            },
+            ("float", [lo, hi]) | ("float", [lo, hi, _]) => Kind::Float {
+                lo: number(lo)?,
+                hi: number(hi)?,
+                digits: args.get(2).map_or(Ok(6), |d| number(d))?,
+            },
+            ("uuid", []) => Kind::Uuid,
+            ("date", [from, to]) | ("date", [from, to, _]) => {
+                let format = args.get(2).unwrap_or(&"%Y-%m-%d").to_string();
+                let mut spec = format.chars();
+                while let Some(c) = spec.next() {
+                    if c == '%' && !matches!(spec.next(), Some('Y' | 'y' | 'm' | 'd' | '%')) {
+                        return Err(format!("unsupported date format {}", format));
+                    }
+                }
+                Kind::Date {
+                    from: date(from)?,
+                    to: date(to)?,
+                    format,
+                }
+            }
+            ("hex", [n]) => Kind::Hex { len: number(n)? },
+            ("pick", [arg]) => match keyword(arg) {
+                Some(("file", path)) => Kind::Pick(ListSource::File(path.to_string())),
+                Some(("dict", name)) if BUNDLED.iter().any(|(n, _)| *n == name) => {
+                    Kind::Pick(ListSource::Bundled(name.to_string()))
+                }
+                _ => return Err(format!("wrong arguments in <@{}>", source)),
+            },
+            ("int" | "float" | "uuid" | "date" | "hex" | "pick", _) => {
+                return Err(format!("wrong arguments in <@{}>", source))
+            }
+            _ => return Err(format!("unknown built-in <@{}>", source)),
+        };
+        if let Kind::Float { lo, hi, digits } = kind {
+            let (scale, first, end) = grid(lo, hi, digits);
+            if ![lo, hi, hi - lo, scale, first, end, end - first]
+                .iter()
+                .all(|x| x.is_finite())
+            {
+                return Err(format!(
+                    "<@{}> needs finite bounds a float can span",
+                    source
+                ));
+            }
+            if first >= end && lo <= hi {
+                return Err(format!(
+                    "<@{}> holds no number with {} decimals",
+                    source, digits
+                ));
+            }
+        }
+        let empty = match kind {
+            Kind::Int { lo, hi } => lo > hi,
+            Kind::Float { lo, hi, .. } => lo > hi,
+            Kind::Date { from, to, .. } => from > to,
+            _ => false,
+        };
+        if empty {
+            return Err(format!("empty range in <@{}>", source));
+        }
+        let b = Builtin {
+            source: source.to_string(),
+            kind,
+        };
+        // a long date format may need too large an automaton
+        if let Some(pattern) = b.pattern() {
+            Regex::new(&pattern).map_err(|e| format!("<@{}>: {}", source, e))?;
+        }
+        Ok(b)
+    }
+
+    // the word list this built-in picks from
+    pub fn list(&self) -> Option<&ListSource> {
+        match &self.kind {
+            Kind::Pick(source) => Some(source),
+            _ => None,
+        }
+    }
+
+    // the rule name standing for this built-in, "@" never occurs in names
+    pub fn key(&self) -> String {
+        format!("<@{}>", self.source)
+    }
+
+    // lists holds the list of a pick
+    pub fn sample(&self, rng: &mut dyn RngCore, lists: &Lists) -> String {
+        match &self.kind {
+            Kind::Pick(source) => lists[source].pick(rng).to_string(),
+            Kind::Numbers(n) => n.sample(rng),
+            Kind::Int { lo, hi } => rng.gen_range(*lo..=*hi).to_string(),
+            Kind::Float { lo, hi, digits } => {
+                let (scale, first, end) = grid(*lo, *hi, *digits);
+                let m = (first + ((end - first) * rng.gen::<f64>()).floor()).min(end - 1.0);
+                // + 0.0 turns -0 into 0
+                format!("{:.*}", digits, m / scale + 0.0)
+            }
+            Kind::Uuid => {
+                let mut b = [0u8; 16];
+                rng.fill_bytes(&mut b);
+                b[6] = b[6] & 0x0f | 0x40;
+                b[8] = b[8] & 0x3f | 0x80;
+                let hex: String = b.iter().map(|x| format!("{:02x}", x)).collect();
+                format!(
+                    "{}-{}-{}-{}-{}",
+                    &hex[..8],
+                    &hex[8..12],
+                    &hex[12..16],
+                    &hex[16..20],
+                    &hex[20..]
+                )
+            }
+            Kind::Date { from, to, format } => {
+                let (y, m, d) = civil_from_days(rng.gen_range(*from..=*to));
+                let mut out = String::new();
+                let mut spec = format.chars();
+                while let Some(c) = spec.next() {
+                    match c {
+                        '%' => match spec.next() {
+                            Some('Y') => out += &format!("{:04}", y),
+                            Some('y') => out += &format!("{:02}", y % 100),
+                            Some('m') => out += &format!("{:02}", m),
+                            Some('d') => out += &format!("{:02}", d),
+                            _ => out.push('%'),
+                        },
+                        c => out.push(c),
+                    }
+                }
+                out
+            }
+            Kind::Hex { len } => (0..*len)
+                .map(|_| char::from_digit(rng.gen_range(0..16), 16).unwrap())
+                .collect(),
+        }
+    }
+
+    // a regex matching every sample, None for picks and hex strings
+    pub fn shape(&self) -> Option<Regex> {
+        let pattern = self.pattern()?;
+        Some(Regex::new(&pattern).expect("shapes are checked in Builtin::new"))
+    }
+
+    fn pattern(&self) -> Option<String> {
+        let sign = |negative: bool| if negative { "-?" } else { "" };
+        let pattern = match &self.kind {
+            Kind::Int { lo, hi } => format!(
+                "{}[0-9]{{1,{}}}",
+                sign(*lo < 0),
+                digits(lo.unsigned_abs().max(hi.unsigned_abs()))
+            ),
+            Kind::Float { lo, hi, digits: d } => {
+                let int = format!("{:.0}", lo.abs().max(hi.abs()).ceil()).len();
+                match d {
+                    0 => format!("{}[0-9]{{1,{}}}", sign(*lo < 0.0), int),
+                    _ => format!("{}[0-9]{{1,{}}}\\.[0-9]{{{}}}", sign(*lo < 0.0), int, d),
+                }
+            }
+            Kind::Uuid => {
+                "[0-9a-f]{8}-[0-9a-f]{4}-4[0-9a-f]{3}-[89ab][0-9a-f]{3}-[0-9a-f]{12}".to_string()
+            }
+            Kind::Date { format, .. } => {
+                let mut out = String::new();
+                let mut spec = format.chars();
+                while let Some(c) = spec.next() {
+                    match c {
+                        '%' => match spec.next() {
+                            Some('Y') => out += "[0-9]{4}",
+                            Some('y' | 'm' | 'd') => out += "[0-9]{2}",
+                            _ => out += "%",
+                        },
+                        c => out += &escape(&c.to_string()),
+                    }
+                }
+                out
+            }
+            Kind::Numbers(n) => n.shape(),
+            Kind::Hex { .. } | Kind::Pick(_) => return None,
+        };
+        Some(pattern)
+    }
+
+    // the built-in as one rule deriving its shape, followed by the shape's rules;
+    // a pick has one alternative per entry of its list, and a hex string of n digits
+    // derives runs of 2^k of them, one for each bit of n, so that it needs no more
+    // than a rule per bit however long it is
+    pub fn rules(&self, lists: &Lists) -> Result<Rules, String> {
+        if let Kind::Hex { len } = self.kind {
+            let run = |k: usize| format!("<@{}#{}>", self.source, k);
+            let digits = "0123456789abcdef".chars();
+            let mut rules = Rules::from([(
+                run(1),
+                digits
+                    .map(|c| vec![Symbol::Terminal(c.to_string())])
+                    .collect(),
+            )]);
+            let mut k = 2;
+            while k <= len {
+                let half = Symbol::NonTerminal(run(k / 2));
+                rules.insert(run(k), vec![vec![half.clone(), half]]);
+                k *= 2;
+            }
+            let runs = (0..usize::BITS)
+                .map(|bit| 1 << bit)
+                .filter(|k| len & k != 0)
+                .map(|k| Symbol::NonTerminal(run(k)))
+                .collect();
+            rules.insert(self.key(), vec![runs]);
+            return Ok(rules);
+        }
+        if let Kind::Pick(source) = &self.kind {
+            let list = lists
+                .get(source)
+                .ok_or_else(|| format!("{:?} of {} is not loaded", source, self.key()))?;
+            let mut words = list.words.clone();
+            words.sort();
+            words.dedup();
+            let alts = words
+                .into_iter()
+                .map(|w| vec![Symbol::Terminal(w)])
+                .collect();
+            return Ok(Rules::from([(self.key(), alts)]));
+        }
+        let shape = self.shape().unwrap();
+        let mut rules = Rules::from([(self.key(), vec![vec![Symbol::NonTerminal(shape.key())]])]);
+        rules.extend(shape.rules());
+        Ok(rules)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn samples_fit_shape() {
+        let mut rng = StdRng::seed_from_u64(9);
+        for source in [
+            "int(-5,120)",
+            "float(0,1,3)",
+            "float(-10,10)",
+            "float(0,1e30,2)",
+            "float(-1e300,1e300,0)",
+            "uuid",
+            "date(2020-01-01,2025-12-31,%d/%m/%y)",
+            "int(dist=zipf, n=1000, s=1.1)",
+            "float(dist=normal, mean=50, sd=10, digits=1)",
+        ] {
+            let b = Builtin::new(source).unwrap();
+            let shape = b.shape().unwrap();
+            for _ in 0..100 {
+                let s = b.sample(&mut rng, &Lists::new());
+                assert!(shape.is_match(&s), "{} gave {}", source, s);
+            }
+        }
+        let b = Builtin::new("int(1,3)").unwrap();
+        let mut seen: Vec<String> = (0..100)
+            .map(|_| b.sample(&mut rng, &Lists::new()))
+            .collect();
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen, ["1", "2", "3"]);
+        let b = Builtin::new("date(2024-02-28,2024-03-01)").unwrap();
+        let mut seen: Vec<String> = (0..100)
+            .map(|_| b.sample(&mut rng, &Lists::new()))
+            .collect();
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen, ["2024-02-28", "2024-02-29", "2024-03-01"]);
+        for bad in [
+            "int(3,1)",
+            "int(1)",
+            "date(2023-02-29,2024-01-01)",
+            "nope",
+            "hex(x)",
+            "float(0,inf)",
+            "float(0.501,0.509,2)",
+            "float(-1e308,1e308)",
+            "float(nan,1)",
+        ] {
+            assert!(Builtin::new(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn long_hex_and_float_grid() {
+        use crate::collection::{Collection, SizeMetric};
+        let mut c = Collection::new();
+        c.add(r#"<h>::=<@hex(2000)>"+"<@hex(3)>"#).unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+        let h = c.gen_with_rng("<h>", &mut rng).unwrap();
+        assert_eq!(h.len(), 2004);
+        assert!(c.accepts("<h>", &h).unwrap());
+        assert!(!c.accepts("<h>", &h[1..]).unwrap());
+        let counts = c.counts("<@hex(3)>", SizeMetric::Text, 3).unwrap();
+        assert_eq!(counts.count_upto("<@hex(3)>", 3).to_string(), "4096");
+        // rounding to the digits would give 1.000 now and then
+        let b = Builtin::new("float(0,1,3)").unwrap();
+        let mut seen: Vec<String> = (0..20000)
+            .map(|_| b.sample(&mut rng, &Lists::new()))
+            .collect();
+        seen.sort();
+        seen.dedup();
+        assert_eq!(
+            (seen.len(), &seen[0][..], &seen[999][..]),
+            (1000, "0.000", "0.999")
+        );
+        let b = Builtin::new("float(-0.5,0.5,0)").unwrap();
+        assert_eq!(b.sample(&mut rng, &Lists::new()), "0");
+    }
+}
@@ -1,10 +1,85 @@
 use rand::Rng;
 
-use crate::parser::{self, parse, Ast, *};
-use std::collections::HashMap;
+use crate::parser::gen::transform_key;
+use crate::parser::{self, Ast, *};
+use crate::transform::{self, Transform};
+use crate::wordlist::{Lists, WordList};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+mod ambiguity;
+mod analysis;
+mod attribute;
+mod binary;
+mod boltzmann;
+mod choice;
+mod computed;
+mod derivation;
+mod earley;
+mod forest;
+mod func;
+mod mutate;
+mod negative;
+mod parallel;
+mod rank;
+mod record;
+mod reduce;
+mod uniform;
+
+pub use ambiguity::{Ambiguity, AmbiguityReport};
+pub use analysis::{uniform_weights, Change, Divergence, SizeReport};
+pub use boltzmann::{Boltzmann, Leaves, Weights};
+pub use choice::{Arbitrary, ByteStream, Chooser, Sampler};
+pub use derivation::Derivation;
+pub use earley::{Mismatch, Recognizer};
+pub use forest::{Forest, ForestChild, ForestNode, Packed, Parse};
+pub use func::{GenContext, GenFn};
+pub use mutate::{Mutation, Mutator};
+pub use negative::{ErrorKind, NearMiss, NegativeSampler, ALL_ERRORS};
+pub use parallel::sample_seed;
+pub use record::{Choice, Choices, Recorder, Replayer};
+pub use uniform::{Counts, SizeMetric};
+
+const DEFAULT_EXPECTED_SIZE: f64 = 50.0;
+
+// flattened view of the rules, "<name>" -> alternatives
+pub type Rules = HashMap<String, Vec<Vec<Symbol>>>;
+
+// the rules reachable from bnf, which must all exist
+fn reached(mut rules: Rules, bnf: &str) -> Result<Rules, String> {
+    let mut reached = Rules::new();
+    let mut todo = vec![bnf.to_string()];
+    while let Some(k) = todo.pop() {
+        if reached.contains_key(&k) {
+            continue;
+        }
+        let alts = rules
+            .remove(&k)
+            .ok_or_else(|| format!("No production rule for {}", k))?;
+        for sym in alts.iter().flatten() {
+            if let Symbol::NonTerminal(t) = sym {
+                todo.push(t.clone());
+            }
+        }
+        reached.insert(k, alts);
+    }
+    Ok(reached)
+}
 
 pub struct Collection {
     h: HashMap<String, Ast>,
+    fns: HashMap<String, Arc<GenFn>>, // "<name>" -> function generating it
+    lists: Lists,                     // word lists of <@pick(...)>, loaded once
+    fields: HashSet<String>,          // rules with computed fields
+    expected_size: f64,
+    binary: bool, // output is written as bytes, see set_binary
+    tuned: RwLock<HashMap<String, Arc<Boltzmann>>>, // start -> branch probabilities
+}
+
+impl Default for Collection {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Collection {
@@ -12,7 +87,30 @@ impl Collection {
         let parse_result = parser::parse(bnf_expr);
         match parse_result {
             Ok(Ast::Bnf(b)) => {
-                self.h.insert(b.term.bnf(), Ast::Bnf(b));
+                let name = b.term.bnf();
+                let ast = Ast::Bnf(b);
+                ast.check_bindings()
+                    .map_err(|e| format!("{} in {}", e, bnf_expr))?;
+                for e0 in ast.expr0s() {
+                    if let Expr0::Builtin { builtin } = e0 {
+                        if let Some(source) = builtin.list() {
+                            if !self.lists.contains_key(source) {
+                                let list = WordList::load(source)?;
+                                self.lists.insert(source.clone(), list);
+                            }
+                        }
+                    }
+                }
+                match ast
+                    .expr0s()
+                    .iter()
+                    .any(|e0| matches!(e0, Expr0::Computed { .. }))
+                {
+                    true => self.fields.insert(name.clone()),
+                    false => self.fields.remove(&name),
+                };
+                self.h.insert(name, ast);
+                self.tuned.write().unwrap().clear();
                 Ok(())
             }
             Err(e) => Err(format!("parse {} failed, error: {}", bnf_expr, e)),
@@ -21,87 +119,404 @@ impl Collection {
     }
 
     pub fn new() -> Self {
-        Self { h: HashMap::new() }
+        Self {
+            h: HashMap::new(),
+            fns: HashMap::new(),
+            lists: Lists::new(),
+            fields: HashSet::new(),
+            expected_size: DEFAULT_EXPECTED_SIZE,
+            binary: false,
+            tuned: RwLock::new(HashMap::new()),
+        }
+    }
+
+    // every referenced nonterminal must have a rule; regex terminals become the
+    // rules of their automaton, built-ins those of their shape
+    pub fn rules(&self) -> Result<Rules, String> {
+        let rules = self.all_rules()?;
+        for sym in rules.values().flatten().flatten() {
+            if let Symbol::NonTerminal(t) = sym {
+                if !rules.contains_key(t) {
+                    return Err(format!("No production rule for {}", t));
+                }
+            }
+        }
+        Ok(rules)
+    }
+
+    fn all_rules(&self) -> Result<Rules, String> {
+        let mut rules: Rules = self
+            .h
+            .iter()
+            .map(|(k, ast)| (k.clone(), ast.alternatives()))
+            .collect();
+        for ast in self.h.values() {
+            for e0 in ast.expr0s() {
+                match e0 {
+                    Expr0::Regex { regex } if !rules.contains_key(&regex.key()) => {
+                        rules.extend(regex.rules())
+                    }
+                    Expr0::Builtin { builtin } if !rules.contains_key(&builtin.key()) => {
+                        rules.extend(builtin.rules(&self.lists)?)
+                    }
+                    Expr0::Computed { field } if !rules.contains_key(&field.key()) => {
+                        rules.extend(field.rules())
+                    }
+                    _ => (),
+                }
+            }
+        }
+        self.fn_rules(&mut rules);
+        let terminals = rules
+            .values()
+            .flatten()
+            .flatten()
+            .filter_map(|sym| match sym {
+                Symbol::Terminal(t) => Some(t.as_str()),
+                _ => None,
+            });
+        let alphabet = transform::alphabet(terminals);
+        for ast in self.h.values() {
+            for e0 in ast.expr0s() {
+                if let Expr0::Transform { transform, expr } = e0 {
+                    let shape = transform.shape(&alphabet);
+                    let key = vec![Symbol::NonTerminal(shape.key())];
+                    rules.insert(transform_key(transform, expr), vec![key]);
+                    rules.extend(shape.rules())
+                }
+            }
+        }
+        Ok(rules)
+    }
+
+    // the rules as generation from bnf sees them, a transformer derives its inner
+    // expr, a registered function is a leaf and so are the values gen makes without
+    // rules; only those reachable from bnf, which must all exist
+    fn gen_rules(&self, bnf: &str) -> Result<Rules, String> {
+        let mut rules = self.all_rules()?;
+        for ast in self.h.values() {
+            for e0 in ast.expr0s() {
+                let (key, alt) = match e0 {
+                    Expr0::Transform { transform, expr } => {
+                        (transform_key(transform, expr), expr.symbols())
+                    }
+                    Expr0::Regex { regex } => (regex.key(), vec![]),
+                    Expr0::Builtin { builtin } => (builtin.key(), vec![]),
+                    Expr0::Computed { field } => (field.key(), vec![]),
+                    _ => continue,
+                };
+                rules.insert(key, vec![alt]);
+            }
+        }
+        self.fn_leaves(&mut rules);
+        reached(rules, bnf)
     }
 
     pub fn gen(&self, bnf: &str) -> Result<String, String> {
-        fn gen_from_ast(ast: &Ast, bnfs: &HashMap<String, Ast>) -> Result<String, String> {
-            let mut stack = Vec::<&Ast>::new();
+        self.gen_with_rng(bnf, &mut rand::thread_rng())
+    }
+
+    // every random choice is drawn from rng, so a seeded rng gives a reproducible sample
+    pub fn gen_with_rng<R: Rng + ?Sized>(&self, bnf: &str, rng: &mut R) -> Result<String, String> {
+        self.gen_weighted(bnf, &self.boltzmann(bnf)?.weights, rng)
+    }
+
+    // alternatives are chosen with the given probabilities, see size_report for whether they terminate
+    pub fn gen_weighted<R: Rng + ?Sized>(
+        &self,
+        bnf: &str,
+        weights: &Weights,
+        rng: &mut R,
+    ) -> Result<String, String> {
+        self.gen_with(bnf, &mut Sampler { weights, rng })
+    }
+
+    // every alternative is chosen by chooser
+    pub fn gen_with<C: Chooser + ?Sized>(
+        &self,
+        bnf: &str,
+        chooser: &mut C,
+    ) -> Result<String, String> {
+        Ok(self.generate(bnf, chooser, false, self.binary)?.0)
+    }
+
+    // like gen_with, but keeping the derivation tree, whose leaves are the texts of
+    // terminals, regexes, built-ins, references and registered functions
+    pub fn gen_derivation_with<C: Chooser + ?Sized>(
+        &self,
+        bnf: &str,
+        chooser: &mut C,
+    ) -> Result<Derivation, String> {
+        Ok(self.generate(bnf, chooser, true, self.binary)?.1.unwrap())
+    }
+
+    fn generate<C: Chooser + ?Sized>(
+        &self,
+        bnf: &str,
+        chooser: &mut C,
+        keep_tree: bool,
+        binary: bool,
+    ) -> Result<(String, Option<Derivation>), String> {
+        fn gen_from_ast<C: Chooser + ?Sized>(
+            ast: &Ast,
+            c: &Collection,
+            chooser: &mut C,
+            keep_tree: bool,
+            binary: bool,
+        ) -> Result<(String, Option<Derivation>), String> {
+            // what is left to do: expand an Ast, leave a rule expansion, bind the
+            // text produced since start, make it a leaf of the tree, or transform it
+            enum Step<'a> {
+                Expand(&'a Ast),
+                Leave,
+                Bound {
+                    var: &'a str,
+                    start: usize,
+                },
+                Leaf {
+                    start: usize,
+                },
+                Transformed {
+                    transform: &'a Transform,
+                    start: usize,
+                },
+            }
+            use Step::*;
+            let mut stack = Vec::<Step>::new();
+            let mut depth = 0;
+            // rule expansions in progress as (rule, alternative, children, start
+            // of the text), and the finished tree; computed fields need the tree
+            let keep_tree = keep_tree || !c.fields.is_empty();
+            let mut nodes: Vec<(String, usize, Vec<Derivation>, usize)> = vec![];
+            let mut root = None;
+            // variables of each rule expansion in progress, innermost last
+            let mut scopes: Vec<HashMap<String, String>> = vec![HashMap::new()];
             let mut text = "".to_string();
-            stack.push(ast);
+            // alternatives to skip in the Stmt chain of the rule being expanded,
+            // the chain is popped right after its Bnf
+            let mut skip = 0;
+            stack.push(Expand(ast));
             while !stack.is_empty() {
-                let top_ast = stack.pop().ok_or_else(|| "Stack error".to_string())?;
+                let top_ast = match stack.pop().ok_or_else(|| "Stack error".to_string())? {
+                    Expand(ast) => ast,
+                    Leave => {
+                        depth -= 1;
+                        scopes.pop();
+                        if let Some((rule, alt, mut children, start)) = nodes.pop() {
+                            let fill = c.fields.contains(&rule);
+                            if fill {
+                                c.fill_fields(&rule, alt, &mut children, binary)?;
+                            }
+                            let node = Derivation::Node {
+                                rule,
+                                alt,
+                                children,
+                            };
+                            if fill {
+                                text.truncate(start);
+                                text += &node.text();
+                            }
+                            match nodes.last_mut() {
+                                Some((_, _, siblings, _)) => siblings.push(node),
+                                None => root = Some(node),
+                            }
+                        }
+                        continue;
+                    }
+                    Leaf { start } => {
+                        if let Some((_, _, children, _)) = nodes.last_mut() {
+                            children.push(Derivation::Leaf(text[start..].to_string()));
+                        }
+                        continue;
+                    }
+                    Transformed { transform, start } => {
+                        let inner = text.split_off(start);
+                        text += &transform.apply(&inner, binary);
+                        // the expansion of the inner expr is one leaf in the tree
+                        if keep_tree {
+                            nodes.pop();
+                            if let Some((_, _, children, _)) = nodes.last_mut() {
+                                children.push(Derivation::Leaf(text[start..].to_string()));
+                            }
+                        }
+                        continue;
+                    }
+                    Bound { var, start } => {
+                        let value = text[start..].to_string();
+                        scopes.last_mut().unwrap().insert(var.to_string(), value);
+                        continue;
+                    }
+                };
                 match top_ast {
                     Ast::Bnf(b) => {
-                        stack.push(&b.stmt);
+                        let n = match &*b.stmt {
+                            Ast::Stmt { parallels, .. } => *parallels as usize,
+                            _ => 1,
+                        };
+                        let rule = b.term.bnf();
+                        skip = chooser.alternative(&rule, n)?;
+                        if keep_tree {
+                            nodes.push((rule, skip, vec![], text.len()));
+                        }
+                        depth += 1;
+                        scopes.push(HashMap::new());
+                        stack.push(Leave);
+                        stack.push(Expand(&b.stmt));
                     }
                     Ast::Expr(Expr::LetterE) => (),
                     Ast::Expr(Expr::Expr0Remain {
                         expr0: e0,
                         remain_expr: r,
                     }) => {
-                        stack.push(r);
-                        stack.push(e0);
+                        stack.push(Expand(r));
+                        stack.push(Expand(e0));
                     }
                     Ast::Expr0(Expr0::Terminal { name: n }) => {
-                        stack.push(n);
+                        stack.push(Leaf { start: text.len() });
+                        stack.push(Expand(n));
+                    }
+                    Ast::Expr0(Expr0::Regex { regex }) => {
+                        stack.push(Leaf { start: text.len() });
+                        text += &regex.sample(chooser)?;
+                    }
+                    Ast::Expr0(Expr0::Builtin { builtin }) => {
+                        stack.push(Leaf { start: text.len() });
+                        text += &chooser.value(&mut |rng| builtin.sample(rng, &c.lists))?;
                     }
-                    Ast::Expr0(Expr0::NonTerminal { term: t }) => match bnfs.get(&t.bnf()) {
-                        Some(ast) => {
-                            stack.push(ast);
+                    Ast::Expr0(Expr0::Bytes { bytes }) => {
+                        stack.push(Leaf { start: text.len() });
+                        text.extend(bytes.iter().map(|b| char::from(*b)));
+                    }
+                    Ast::Expr0(Expr0::Transform { transform, expr }) => {
+                        if keep_tree {
+                            nodes.push((String::new(), 0, vec![], text.len()));
                         }
-                        None => {
-                            return Err(format!("No production rule for {}", t.bnf()));
+                        stack.push(Transformed {
+                            transform,
+                            start: text.len(),
+                        });
+                        stack.push(Expand(expr));
+                    }
+                    Ast::Expr0(Expr0::Computed { .. }) => {
+                        // a placeholder, filled in when its rule expansion is left
+                        stack.push(Leaf { start: text.len() });
+                    }
+                    Ast::Expr0(Expr0::Bind { expr0, var }) => {
+                        stack.push(Bound {
+                            var,
+                            start: text.len(),
+                        });
+                        stack.push(Expand(expr0));
+                    }
+                    Ast::Expr0(Expr0::Ref { var }) => {
+                        stack.push(Leaf { start: text.len() });
+                        let value = scopes.last().unwrap().get(var);
+                        text += value.ok_or_else(|| format!("unbound variable ${}", var))?;
+                    }
+                    Ast::Expr0(Expr0::NonTerminal { term: t }) => {
+                        let key = t.bnf();
+                        match (c.fns.get(&key), c.h.get(&key)) {
+                            (Some(f), _) => {
+                                stack.push(Leaf { start: text.len() });
+                                text += &chooser.value(&mut |rng| {
+                                    f(&mut GenContext {
+                                        rng,
+                                        depth,
+                                        bindings: scopes.last().unwrap(),
+                                    })
+                                })?;
+                            }
+                            (None, Some(ast)) => {
+                                stack.push(Expand(ast));
+                            }
+                            (None, None) => {
+                                return Err(format!("No production rule for {}", key));
+                            }
                         }
-                    },
+                    }
                     Ast::Name(Name::Epsilon) => (),
                     Ast::Name(Name::HeadTail { head: h, tail: t }) => {
-                        stack.push(t);
+                        stack.push(Expand(t));
                         text += h;
                     }
                     Ast::RemainExpr(RemainExpr::Epsilon) => (),
                     Ast::RemainExpr(RemainExpr::Expr { expr: e }) => {
-                        stack.push(e);
+                        stack.push(Expand(e));
                     }
                     Ast::RemainStmt(RemainStmt::Epsilon) => (),
                     Ast::RemainStmt(RemainStmt::OrStmt { stmt: s }) => {
-                        stack.push(s);
+                        stack.push(Expand(s));
                     }
                     Ast::Stmt {
                         expr: e,
                         remain_stmt: r,
-                        parallels: par,
+                        ..
                     } => {
-                        if let Ast::RemainStmt(RemainStmt::OrStmt { .. }) = &**r {
-                            let mut rng = rand::thread_rng();
-
-                            let rnd: f32 = rng.gen();
-                            // the more length we have, the less we tend to jump
-                            let p = 1.0 / ((1 + text.len()) as f32 / 50.0 + 1f32);
-                            let uniform = (par - 1) as f32 / *par as f32;
-                            //println!("par {} ast {}", par, r.bnf());
-                            if rnd < p && rnd < uniform {
-                                stack.push(r);
-                            } else {
-                                stack.push(e);
-                            }
+                        if skip > 0 {
+                            skip -= 1;
+                            stack.push(Expand(r));
                         } else {
-                            stack.push(e);
+                            stack.push(Expand(e));
                         }
                     }
                     Ast::Term { name: n } => {
-                        stack.push(n);
+                        stack.push(Expand(n));
                     }
                     Ast::Epsilon => (),
                 };
             }
-            Ok(text)
+            Ok((text, root))
         }
 
+        if let Some(f) = self.fns.get(bnf) {
+            let bindings = HashMap::new();
+            let text = chooser.value(&mut |rng| {
+                f(&mut GenContext {
+                    rng,
+                    depth: 0,
+                    bindings: &bindings,
+                })
+            })?;
+            let tree = keep_tree.then(|| Derivation::Leaf(text.clone()));
+            return Ok((text, tree));
+        }
         let ast = self
             .h
             .get(bnf)
             .ok_or_else(|| format!("No production rule for {}", bnf))?;
-        gen_from_ast(ast, &self.h)
+        gen_from_ast(ast, self, chooser, keep_tree, binary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn bindings() {
+        let mut c = Collection::new();
+        c.add(r#"<el>::="+"<tag as t>" "<body>"-"$t"#).unwrap();
+        c.add(r#"<body>::=<el>" "<body>|E"#).unwrap();
+        c.add(r#"<tag>::="a"|"b"|"c""#).unwrap();
+        let mut rng = StdRng::seed_from_u64(6);
+        for _ in 0..30 {
+            let (s, choices) = c.gen_recorded("<el>", &mut rng).unwrap();
+            // each close tag matches its open tag, nested <el>s have their own $t
+            let mut open = vec![];
+            for word in s.split(' ') {
+                if let Some(tag) = word.strip_prefix('+') {
+                    open.push(tag.to_string());
+                } else if let Some(tag) = word.strip_prefix('-') {
+                    assert_eq!(open.pop().as_deref(), Some(tag), "{}", s);
+                }
+            }
+            assert!(open.is_empty(), "{}", s);
+            assert!(c.accepts("<el>", &s).unwrap());
+            assert_eq!(c.replay("<el>", &choices).unwrap(), s);
+        }
+        assert!(c.add("<x>::=$t<tag as t>").is_err());
+        assert!(c.add("<x>::=<tag as t>|$t").is_err());
     }
 }
@@ -0,0 +1,85 @@
+// ambiguity detection by bounded search
+//
+// Derivations are enumerated in rank order, size by size. Two derivations of the
+// same text have the same length, so texts only need to be remembered for the
+// size being enumerated. Finding none is evidence, not proof: the grammar is
+// unambiguous for texts up to the bound.
+use super::{Collection, Counts, Derivation, SizeMetric};
+use crate::bigint::BigUint;
+use std::collections::HashMap;
+
+pub struct AmbiguityReport {
+    pub bound: usize,
+    pub checked: BigUint, // derivations enumerated
+    pub witness: Option<Ambiguity>,
+}
+
+// a text with two distinct derivation trees
+pub struct Ambiguity {
+    pub text: String,
+    pub trees: (Derivation, Derivation),
+}
+
+impl Collection {
+    // the shortest ambiguous text of bnf, within texts of length at most bound
+    pub fn ambiguity(&self, bnf: &str, bound: usize) -> Result<AmbiguityReport, String> {
+        // built-ins are parsed by their shape, so their shapes are searched too
+        let counts = Counts::new(self.rules()?, SizeMetric::Text, bound)
+            .map_err(|s| format!("ambiguous, {}", s))?;
+        let mut checked = BigUint::zero();
+        for n in 0..=bound {
+            let mut seen: HashMap<String, Derivation> = HashMap::new();
+            let total = counts.count(bnf, n);
+            let mut k = BigUint::zero();
+            while k < total {
+                let d = counts.unrank_exact(bnf, n, &k)?;
+                checked += &BigUint::one();
+                let text = d.text();
+                if let Some(first) = seen.remove(&text) {
+                    return Ok(AmbiguityReport {
+                        bound,
+                        checked,
+                        witness: Some(Ambiguity {
+                            text,
+                            trees: (first, d),
+                        }),
+                    });
+                }
+                seen.insert(text, d);
+                k += &BigUint::one();
+            }
+        }
+        Ok(AmbiguityReport {
+            bound,
+            checked,
+            witness: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shortest_witness() {
+        let mut c = Collection::new();
+        c.add(r#"<e>::=<e>"-"<e>|"1""#).unwrap();
+        let r = c.ambiguity("<e>", 10).unwrap();
+        let w = r.witness.unwrap();
+        assert_eq!(w.text, "1-1-1");
+        assert_ne!(w.trees.0, w.trees.1);
+        assert_eq!(w.trees.1.text(), "1-1-1");
+    }
+
+    #[test]
+    fn unambiguous_within_bound() {
+        let mut c = Collection::new();
+        c.add(r#"<e>::=<e>"-"<n>|<n>"#).unwrap();
+        c.add(r#"<n>::="1"|"2""#).unwrap();
+        let r = c.ambiguity("<e>", 9).unwrap();
+        assert!(r.witness.is_none());
+        // 2 + 4 + 8 + 16 + 32 texts of odd length up to 9
+        assert_eq!(r.checked, BigUint::from(62));
+    }
+}
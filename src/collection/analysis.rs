@@ -0,0 +1,497 @@
+// size analysis of a grammar under given branch probabilities
+//
+// Under fixed branch probabilities, expanding a nonterminal is a branching process.
+// Its expected size is finite exactly when every recursive component expands into
+// less than one copy of itself on average (spectral radius of its mean matrix < 1).
+use super::{Collection, Leaves, Rules, SizeMetric, Weights};
+use crate::parser::Symbol;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+// nonterminals of an alternative, with repetition
+pub(crate) fn nonterminals(alt: &[Symbol]) -> impl Iterator<Item = &String> {
+    alt.iter().filter_map(|s| match s {
+        Symbol::NonTerminal(t) => Some(t),
+        _ => None,
+    })
+}
+
+// nonterminals that derive at least one finite string
+pub fn productive(rules: &Rules) -> HashSet<String> {
+    productive_by(rules, |_, _| true)
+}
+
+// the same through alternatives accepted by keep
+pub(crate) fn productive_by(rules: &Rules, keep: impl Fn(&str, usize) -> bool) -> HashSet<String> {
+    let mut p = HashSet::new();
+    loop {
+        let before = p.len();
+        for (k, alts) in rules {
+            let finishes = |(i, a): (usize, &Vec<Symbol>)| {
+                keep(k, i) && nonterminals(a).all(|t| p.contains(t))
+            };
+            if !p.contains(k) && alts.iter().enumerate().any(finishes) {
+                p.insert(k.clone());
+            }
+        }
+        if p.len() == before {
+            return p;
+        }
+    }
+}
+
+// least text size of every productive nonterminal with an alternative reaching it;
+// only strict improvements are taken, so the chosen alternatives never form a cycle
+pub(crate) fn min_alternatives(rules: &Rules) -> HashMap<String, (usize, usize)> {
+    let mut best: HashMap<String, (usize, usize)> = HashMap::new();
+    let mut names: Vec<&String> = rules.keys().collect();
+    names.sort();
+    loop {
+        let mut changed = false;
+        for &k in &names {
+            for (a, alt) in rules[k].iter().enumerate() {
+                let size = nonterminals(alt).try_fold(SizeMetric::Text.base(alt), |acc, t| {
+                    best.get(t).map(|b| acc + b.0)
+                });
+                if let Some(s) = size {
+                    if best.get(k).is_none_or(|b| s < b.0) {
+                        best.insert(k.clone(), (s, a));
+                        changed = true;
+                    }
+                }
+            }
+        }
+        if !changed {
+            return best;
+        }
+    }
+}
+
+// nonterminals reachable from bnf through alternatives accepted by keep, sorted
+pub(crate) fn reachable(
+    rules: &Rules,
+    bnf: &str,
+    keep: impl Fn(&str, usize) -> bool,
+) -> Vec<String> {
+    let mut seen = HashSet::from([bnf.to_string()]);
+    let mut todo = vec![bnf.to_string()];
+    while let Some(k) = todo.pop() {
+        for (i, alt) in rules[&k].iter().enumerate() {
+            if keep(&k, i) {
+                for t in nonterminals(alt) {
+                    if seen.insert(t.clone()) {
+                        todo.push(t.clone());
+                    }
+                }
+            }
+        }
+    }
+    let mut v: Vec<String> = seen.into_iter().collect();
+    v.sort();
+    v
+}
+
+// solve a x = b by gaussian elimination, None when a is singular
+pub(crate) fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-300 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        let (top, bottom) = a.split_at_mut(col + 1);
+        let p = &top[col];
+        for (row, r) in bottom.iter_mut().enumerate() {
+            let f = r[col] / p[col];
+            if f != 0.0 {
+                for (x, y) in r[col..].iter_mut().zip(&p[col..]) {
+                    *x -= f * y;
+                }
+                b[col + 1 + row] -= f * b[col];
+            }
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let s: f64 = (row + 1..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - s) / a[row][row];
+    }
+    x.iter().all(|v| v.is_finite()).then_some(x)
+}
+
+// I - m is invertible with a nonnegative inverse exactly when the spectral radius of
+// the nonnegative m is below 1, checked through (I - m) z = 1 having a positive solution
+pub(crate) fn subcritical(m: &[Vec<f64>]) -> bool {
+    let n = m.len();
+    let a = (0..n)
+        .map(|i| (0..n).map(|j| (i == j) as u8 as f64 - m[i][j]).collect())
+        .collect();
+    solve(a, vec![1.0; n]).is_some_and(|z| z.iter().all(|&v| v > 0.0))
+}
+
+// strongly connected components over alternatives with positive probability,
+// callees before callers
+fn components(rules: &Rules, weights: &Weights) -> Vec<Vec<String>> {
+    struct Tarjan<'a> {
+        rules: &'a Rules,
+        weights: &'a Weights,
+        index: HashMap<String, usize>,
+        low: HashMap<String, usize>,
+        stack: Vec<String>,
+        on_stack: HashSet<String>,
+        out: Vec<Vec<String>>,
+    }
+    impl Tarjan<'_> {
+        fn visit(&mut self, k: &String) {
+            let i = self.index.len();
+            self.index.insert(k.clone(), i);
+            self.low.insert(k.clone(), i);
+            self.stack.push(k.clone());
+            self.on_stack.insert(k.clone());
+            let rules = self.rules;
+            for (alt, &p) in rules[k].iter().zip(&self.weights[k]) {
+                if p <= 0.0 {
+                    continue;
+                }
+                for t in nonterminals(alt) {
+                    if !self.index.contains_key(t) {
+                        self.visit(t);
+                        let l = self.low[k].min(self.low[t]);
+                        self.low.insert(k.clone(), l);
+                    } else if self.on_stack.contains(t) {
+                        let l = self.low[k].min(self.index[t]);
+                        self.low.insert(k.clone(), l);
+                    }
+                }
+            }
+            if self.low[k] == self.index[k] {
+                let mut c = vec![];
+                while let Some(t) = self.stack.pop() {
+                    self.on_stack.remove(&t);
+                    let last = t == *k;
+                    c.push(t);
+                    if last {
+                        break;
+                    }
+                }
+                c.sort();
+                self.out.push(c);
+            }
+        }
+    }
+    let mut keys: Vec<&String> = weights.keys().filter(|k| rules.contains_key(*k)).collect();
+    keys.sort();
+    let mut t = Tarjan {
+        rules,
+        weights,
+        index: HashMap::new(),
+        low: HashMap::new(),
+        stack: vec![],
+        on_stack: HashSet::new(),
+        out: vec![],
+    };
+    for k in keys {
+        if !t.index.contains_key(k) {
+            t.visit(k);
+        }
+    }
+    t.out
+}
+
+pub struct SizeReport {
+    pub expected: HashMap<String, f64>, // infinite where generation does not terminate on average
+    pub divergent: Vec<Divergence>,
+}
+
+// a set of mutually recursive rules that on average expand into at least one copy of the set
+pub struct Divergence {
+    pub rules: Vec<String>,
+    pub radius: f64,          // growth factor per expansion, must be below 1
+    pub changes: Vec<Change>, // weights bringing radius to TARGET_RADIUS, empty if impossible
+}
+
+pub struct Change {
+    pub rule: String,
+    pub alt: usize,
+    pub text: String,
+    pub from: f64,
+    pub to: f64,
+}
+
+const TARGET_RADIUS: f64 = 0.9;
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} expand into {:.3} copies of themselves on average (needs < 1), generation is likely to blow up",
+            self.rules.join(" "),
+            self.radius
+        )?;
+        if self.changes.is_empty() {
+            return write!(
+                f,
+                "\n  no alternative leaves the cycle, add a non recursive alternative to one of the rules"
+            );
+        }
+        write!(f, "\n  e.g. change branch probabilities to")?;
+        for c in &self.changes {
+            write!(
+                f,
+                "\n  {} alternative {} ({}): {:.4} -> {:.4}",
+                c.rule, c.alt, c.text, c.from, c.to
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn alt_bnf(alt: &[Symbol]) -> String {
+    if alt.is_empty() {
+        return "E".to_string();
+    }
+    alt.iter()
+        .map(|s| match s {
+            Symbol::Terminal(t) => format!("\"{}\"", t),
+            Symbol::NonTerminal(t) => t.clone(),
+        })
+        .collect()
+}
+
+// mean matrix of a component: m[i][j] is the expected number of comp[j] in one expansion of comp[i]
+fn mean_matrix(rules: &Rules, weights: &Weights, comp: &[String]) -> Vec<Vec<f64>> {
+    let idx: HashMap<&String, usize> = comp.iter().enumerate().map(|(i, k)| (k, i)).collect();
+    let mut m = vec![vec![0.0; comp.len()]; comp.len()];
+    for (i, k) in comp.iter().enumerate() {
+        for (alt, &p) in rules[k].iter().zip(&weights[k]) {
+            for t in nonterminals(alt) {
+                if let Some(&j) = idx.get(t) {
+                    m[i][j] += p;
+                }
+            }
+        }
+    }
+    m
+}
+
+// power iteration on (I + m) / 2, which is primitive for an irreducible m
+fn spectral_radius(m: &[Vec<f64>]) -> f64 {
+    let n = m.len();
+    let mut v = vec![1.0; n];
+    let mut lambda = 0.0;
+    for _ in 0..100_000 {
+        let w: Vec<f64> = (0..n)
+            .map(|i| (v[i] + (0..n).map(|j| m[i][j] * v[j]).sum::<f64>()) / 2.0)
+            .collect();
+        let l = w.iter().cloned().fold(0.0, f64::max);
+        if l == 0.0 {
+            return 0.0;
+        }
+        v = w.iter().map(|x| x / l).collect();
+        if (l - lambda).abs() <= 1e-12 * l {
+            lambda = l;
+            break;
+        }
+        lambda = l;
+    }
+    2.0 * lambda - 1.0
+}
+
+// scale recursive alternatives of the component down by s against the ones leaving it,
+// with s found by bisection so that the radius becomes TARGET_RADIUS
+fn suggest(rules: &Rules, weights: &Weights, comp: &[String]) -> Vec<Change> {
+    let recursive = |alt: &[Symbol]| nonterminals(alt).any(|t| comp.contains(t));
+    let scaled = |s: f64| -> Weights {
+        comp.iter()
+            .map(|k| {
+                let alts = &rules[k];
+                let exits = alts.iter().zip(&weights[k]).filter(|(a, _)| !recursive(a));
+                let exit_total: f64 = exits.clone().map(|(_, p)| p).sum();
+                let n_exits = exits.count();
+                let w: Vec<f64> = alts
+                    .iter()
+                    .zip(&weights[k])
+                    .map(|(a, &p)| match (recursive(a), exit_total > 0.0) {
+                        (true, _) => p * s,
+                        (false, true) => p,
+                        (false, false) => 1.0 / n_exits as f64,
+                    })
+                    .collect();
+                let total: f64 = w.iter().sum();
+                (k.clone(), w.iter().map(|p| p / total).collect())
+            })
+            .collect()
+    };
+    let radius = |s: f64| spectral_radius(&mean_matrix(rules, &scaled(s), comp));
+    if radius(1e-9) >= TARGET_RADIUS {
+        return vec![];
+    }
+    let (mut lo, mut hi) = (1e-9, 1.0);
+    for _ in 0..60 {
+        let mid = (lo + hi) / 2.0;
+        if radius(mid) < TARGET_RADIUS {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    let new = scaled(lo);
+    let mut changes = vec![];
+    for k in comp {
+        for (i, alt) in rules[k].iter().enumerate() {
+            let (from, to) = (weights[k][i], new[k][i]);
+            if (from - to).abs() > 1e-4 {
+                changes.push(Change {
+                    rule: k.clone(),
+                    alt: i,
+                    text: alt_bnf(alt),
+                    from,
+                    to,
+                });
+            }
+        }
+    }
+    changes
+}
+
+// E[A] = sum over alternatives of p * (base + sum E[C]), for every nonterminal in weights.
+// Solved one component at a time, infinite where the component is not subcritical
+// or uses an infinite one. A leaf has its given size.
+pub fn size_report(
+    rules: &Rules,
+    metric: SizeMetric,
+    weights: &Weights,
+    leaves: &Leaves,
+) -> SizeReport {
+    let mut e: HashMap<String, f64> = HashMap::new();
+    let mut divergent = vec![];
+    for comp in components(rules, weights) {
+        if let Some(&l) = leaves.get(&comp[0]) {
+            e.insert(comp[0].clone(), l);
+            continue;
+        }
+        let n = comp.len();
+        let m = mean_matrix(rules, weights, &comp);
+        let mut b = vec![0.0; n];
+        for (i, k) in comp.iter().enumerate() {
+            for (alt, &p) in rules[k].iter().zip(&weights[k]) {
+                if p <= 0.0 {
+                    continue;
+                }
+                b[i] += p * metric.base(alt) as f64;
+                for t in nonterminals(alt).filter(|t| !comp.contains(t)) {
+                    b[i] += p * e[t];
+                }
+            }
+        }
+        let sol = if !subcritical(&m) {
+            divergent.push(Divergence {
+                radius: spectral_radius(&m),
+                changes: suggest(rules, weights, &comp),
+                rules: comp.clone(),
+            });
+            None
+        } else if b.iter().all(|v| v.is_finite()) {
+            let a = (0..n)
+                .map(|i| (0..n).map(|j| (i == j) as u8 as f64 - m[i][j]).collect())
+                .collect();
+            solve(a, b)
+        } else {
+            None
+        };
+        for (i, k) in comp.iter().enumerate() {
+            e.insert(
+                k.clone(),
+                sol.as_ref().map_or(f64::INFINITY, |s| s[i].max(0.0)),
+            );
+        }
+    }
+    SizeReport {
+        expected: e,
+        divergent,
+    }
+}
+
+// every alternative equally likely
+pub fn uniform_weights(rules: &Rules) -> Weights {
+    rules
+        .iter()
+        .map(|(k, alts)| (k.clone(), vec![1.0 / alts.len() as f64; alts.len()]))
+        .collect()
+}
+
+impl Collection {
+    pub fn size_report(&self, metric: SizeMetric, weights: &Weights) -> Result<SizeReport, String> {
+        let rules = self.rules()?;
+        for (k, w) in weights {
+            let alts = rules
+                .get(k)
+                .ok_or_else(|| format!("No production rule for {}", k))?;
+            if w.len() != alts.len() {
+                return Err(format!(
+                    "{} has {} alternatives, not {}",
+                    k,
+                    alts.len(),
+                    w.len()
+                ));
+            }
+            let used = alts.iter().zip(w).filter(|(_, &p)| p > 0.0);
+            if let Some(t) = used
+                .flat_map(|(a, _)| nonterminals(a))
+                .find(|t| !weights.contains_key(*t))
+            {
+                return Err(format!("No branch probabilities for {}", t));
+            }
+        }
+        Ok(size_report(&rules, metric, weights, &Leaves::new()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collection(bnfs: &[&str]) -> Collection {
+        let mut c = Collection::new();
+        bnfs.iter().for_each(|b| c.add(b).unwrap());
+        c
+    }
+
+    #[test]
+    fn subcritical_sizes() {
+        // <l> is "x" followed by a geometric number of "x"
+        let c = collection(&[r#"<l>::="x"<l>|"x""#, r#"<s>::=<l>"y""#]);
+        let w = uniform_weights(&c.rules().unwrap());
+        let r = c.size_report(SizeMetric::Text, &w).unwrap();
+        assert!((r.expected["<l>"] - 2.0).abs() < 1e-9);
+        assert!((r.expected["<s>"] - 3.0).abs() < 1e-9);
+        assert!(r.divergent.is_empty());
+    }
+
+    #[test]
+    fn supercritical_with_suggestion() {
+        let c = collection(&[
+            r#"<e>::=<e>"+"<e>|<e>"*"<e>|<d>"#,
+            r#"<d>::="1"|"2""#,
+            r#"<s>::=<e>"#,
+        ]);
+        let rules = c.rules().unwrap();
+        let w = uniform_weights(&rules);
+        let r = c.size_report(SizeMetric::Text, &w).unwrap();
+        assert!(r.expected["<e>"].is_infinite());
+        assert!(r.expected["<s>"].is_infinite());
+        assert!(r.expected["<d>"].is_finite());
+        assert_eq!(r.divergent.len(), 1);
+        let d = &r.divergent[0];
+        assert_eq!(d.rules, vec!["<e>"]);
+        assert!((d.radius - 4.0 / 3.0).abs() < 1e-6);
+        let mut fixed = w.clone();
+        for ch in &d.changes {
+            fixed.get_mut(&ch.rule).unwrap()[ch.alt] = ch.to;
+        }
+        let r = c.size_report(SizeMetric::Text, &fixed).unwrap();
+        assert!(r.divergent.is_empty() && r.expected["<s>"].is_finite());
+    }
+}
@@ -0,0 +1,172 @@
+// synthesized attributes, the actions of the chosen alternatives evaluated bottom-up
+// over the derivation tree of a sample, see src/action.rs
+use super::{Chooser, Collection, Derivation, Sampler};
+use crate::action::{Action, Value};
+use rand::Rng;
+use std::collections::HashMap;
+
+type Actions<'a> = Vec<(Option<&'a Action>, HashMap<&'a str, usize>)>;
+// a node with an action being evaluated: the action, its names, the children and
+// the values of those done so far
+type Frame<'a> = (
+    &'a Action,
+    HashMap<&'a str, usize>,
+    &'a [Derivation],
+    Vec<Value>,
+);
+
+impl Collection {
+    // a sample and the attribute of its start symbol
+    pub fn gen_attributed<R: Rng + ?Sized>(
+        &self,
+        bnf: &str,
+        rng: &mut R,
+    ) -> Result<(String, Value), String> {
+        let weights = &self.boltzmann(bnf)?.weights;
+        self.gen_attributed_with(bnf, &mut Sampler { weights, rng })
+    }
+
+    pub fn gen_attributed_with<C: Chooser + ?Sized>(
+        &self,
+        bnf: &str,
+        chooser: &mut C,
+    ) -> Result<(String, Value), String> {
+        let tree = self.gen_derivation_with(bnf, chooser)?;
+        Ok((tree.text(), self.attribute(&tree)?))
+    }
+
+    // a leaf, and a node whose alternative has no action, is its text; post-order
+    // with an explicit stack, trees are as deep as the text is long
+    pub fn attribute(&self, tree: &Derivation) -> Result<Value, String> {
+        let mut cache = HashMap::new();
+        let mut frames: Vec<Frame> = vec![];
+        let mut todo = tree;
+        loop {
+            let mut value = match self.frame(&mut cache, todo)? {
+                Some(f) => {
+                    frames.push(f);
+                    None
+                }
+                None => Some(Value::Str(todo.text())),
+            };
+            loop {
+                let f = match frames.last_mut() {
+                    Some(f) => f,
+                    None => return Ok(value.unwrap()),
+                };
+                f.3.extend(value.take());
+                match f.2.get(f.3.len()) {
+                    Some(child) => {
+                        todo = child;
+                        break;
+                    }
+                    None => {
+                        let (action, names, _, args) = frames.pop().unwrap();
+                        value = Some(action.eval(&args, &names)?);
+                    }
+                }
+            }
+        }
+    }
+
+    // the frame of a node whose alternative has an action
+    fn frame<'a>(
+        &'a self,
+        cache: &mut HashMap<&'a str, Actions<'a>>,
+        tree: &'a Derivation,
+    ) -> Result<Option<Frame<'a>>, String> {
+        let (rule, alt, children) = match tree {
+            Derivation::Leaf(_) => return Ok(None),
+            Derivation::Node {
+                rule,
+                alt,
+                children,
+            } => (rule, *alt, children),
+        };
+        if !cache.contains_key(rule.as_str()) {
+            let ast = self
+                .h
+                .get(rule)
+                .ok_or_else(|| format!("No production rule for {}", rule))?;
+            cache.insert(rule, ast.actions());
+        }
+        Ok(match cache[rule.as_str()].get(alt) {
+            Some((Some(action), names)) => Some((*action, names.clone(), children, vec![])),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn arithmetic_and_checksum() {
+        let mut c = Collection::new();
+        c.add(r#"<sum>::=<prod as a>"+"<sum as b>{$a+$b}|<prod>{$1}"#)
+            .unwrap();
+        c.add(r#"<prod>::=<num>"*"<prod>{$1*$3}|<num>"#).unwrap();
+        c.add("<num>::=/[1-9]/").unwrap();
+        c.add(r#"<rec>::=<@hex(6) as body>" "{cat($body, ' ', hex(sum($body) % 256))}"#)
+            .unwrap();
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..30 {
+            let (text, value) = c.gen_attributed("<sum>", &mut rng).unwrap();
+            let expected: i64 = text
+                .split('+')
+                .map(|p| {
+                    p.split('*')
+                        .map(|n| n.parse::<i64>().unwrap())
+                        .product::<i64>()
+                })
+                .sum();
+            assert_eq!(value, Value::Int(expected), "{}", text);
+            let (text, value) = c.gen_attributed("<rec>", &mut rng).unwrap();
+            let sum: u32 = text.trim().bytes().map(u32::from).sum();
+            assert_eq!(
+                value.to_string(),
+                format!("{} {:x}", text.trim(), sum % 256)
+            );
+        }
+        assert_eq!(
+            c.h["<sum>"].bnf(),
+            r#"[BNF] <sum> ::= <prod as a> "+" <sum as b> {$a+$b} | <prod> {$1} "#
+        );
+        assert!(c.add("<x>::=<num>{$2}").is_err());
+        assert!(c.add("<x>::=<num>{$n}").is_err());
+        assert!(c.add("<x>::=<num>{nope($1)}").is_err());
+        c.add(r#"<x>::="a"{$1/0}"#).unwrap();
+        assert!(c.gen_attributed("<x>", &mut rng).is_err());
+    }
+
+    #[test]
+    fn deep() {
+        let mut c = Collection::new();
+        c.add(r#"<n>::="s"<n>{$2+1}|"0"{0}"#).unwrap();
+        let n = 5000;
+        let mut tree = Derivation::Node {
+            rule: "<n>".to_string(),
+            alt: 1,
+            children: vec![Derivation::Leaf("0".to_string())],
+        };
+        for _ in 0..n {
+            tree = Derivation::Node {
+                rule: "<n>".to_string(),
+                alt: 0,
+                children: vec![Derivation::Leaf("s".to_string()), tree],
+            };
+        }
+        // on a stack far too small to recurse that deep
+        std::thread::scope(|s| {
+            std::thread::Builder::new()
+                .stack_size(64 * 1024)
+                .spawn_scoped(s, || assert_eq!(c.attribute(&tree).unwrap(), Value::Int(n)))
+                .unwrap()
+                .join()
+                .unwrap()
+        });
+    }
+}
@@ -0,0 +1,84 @@
+// binary output: a sample is text with one char U+0000..U+00FF per byte, written
+// out as those bytes, see codec::to_bytes
+use super::{Collection, Sampler};
+use crate::codec::to_bytes;
+use rand::Rng;
+use std::io::Write;
+
+impl Collection {
+    // whether samples are written as bytes, computed fields measure text the way
+    // it is written: a char below U+0100 as one byte, or else as UTF-8
+    pub fn set_binary(&mut self, binary: bool) {
+        self.binary = binary;
+    }
+
+    pub fn gen_bytes(&self, bnf: &str) -> Result<Vec<u8>, String> {
+        self.gen_bytes_with_rng(bnf, &mut rand::thread_rng())
+    }
+
+    pub fn gen_bytes_with_rng<R: Rng + ?Sized>(
+        &self,
+        bnf: &str,
+        rng: &mut R,
+    ) -> Result<Vec<u8>, String> {
+        let weights = &self.boltzmann(bnf)?.weights;
+        let mut sampler = Sampler { weights, rng };
+        Ok(to_bytes(&self.generate(bnf, &mut sampler, false, true)?.0))
+    }
+
+    // one sample written to out
+    pub fn gen_write<R: Rng + ?Sized, W: Write + ?Sized>(
+        &self,
+        bnf: &str,
+        rng: &mut R,
+        out: &mut W,
+    ) -> Result<(), String> {
+        out.write_all(&self.gen_bytes_with_rng(bnf, rng)?)
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::from_bytes;
+    use crate::computed::crc32;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn png_like_chunks() {
+        let mut c = Collection::new();
+        c.add(r#"<png>::=0x89x"504e470d0a1a0a"<chunks>x"0000000049454e44ae426082""#)
+            .unwrap();
+        c.add("<chunks>::=<chunk><chunks>|<chunk>").unwrap();
+        // the length covers the chunk type too, unlike png
+        c.add("<chunk>::=<@len(<body>,u32be)><body as b><@crc32($b,u32be)>")
+            .unwrap();
+        c.add(r#"<body>::="tEXt"<data>"#).unwrap();
+        c.add("<data>::=0x00-0xff<data>|E").unwrap();
+        assert_eq!(
+            c.h["<data>"].bnf(),
+            r"[BNF] <data> ::= /[\x00-\xff]/ <data> | "
+        );
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..20 {
+            let mut out = vec![];
+            c.gen_write("<png>", &mut rng, &mut out).unwrap();
+            assert!(out.starts_with(b"\x89PNG\r\n\x1a\n") && out.ends_with(b"IEND\xaeB`\x82"));
+            let mut rest = &out[8..out.len() - 12];
+            while !rest.is_empty() {
+                let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+                let body = &rest[4..4 + len];
+                assert_eq!(&body[..4], b"tEXt");
+                let crc = u32::from_be_bytes(rest[4 + len..8 + len].try_into().unwrap());
+                assert_eq!(crc, crc32(body));
+                rest = &rest[8 + len..];
+            }
+            assert!(c.accepts("<png>", &from_bytes(&out)).unwrap());
+        }
+        assert!(c.add("<x>::=0x8").is_err());
+        assert!(c.add(r#"<x>::=x"abc""#).is_err());
+        assert!(c.add("<x>::=0x20-0x10").is_err());
+    }
+}
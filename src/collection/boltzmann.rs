@@ -0,0 +1,444 @@
+// Boltzmann branch probabilities
+//
+// With the generating function A(x) = sum over alternatives of x^base * prod C(x)
+// for its nonterminals C, choosing an alternative with probability
+// x^base * prod C(x) / A(x) makes a derivation of size n appear with probability
+// x^n / A(x). The expected size grows with x and is finite below the radius of
+// convergence, so x is tuned by bisection to hit the wanted expected size.
+//
+// Regexes, built-ins and computed fields are not derived through rules by gen, so
+// they enter the system as leaves: x^L for a value of expected text size L.
+use super::analysis::{self, nonterminals, solve, subcritical, SizeReport};
+use super::{Collection, GenContext, Rules, SizeMetric};
+use crate::parser::Expr0;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+// "<name>" -> probability of each alternative
+pub type Weights = HashMap<String, Vec<f64>>;
+
+// "<name>" -> expected text size of a value gen makes without expanding its rule
+pub type Leaves = HashMap<String, f64>;
+
+// values drawn to estimate the expected size of a built-in or registered function
+const SAMPLES: usize = 1000;
+
+pub struct Boltzmann {
+    pub x: f64,
+    pub weights: Weights,
+    pub expected: f64, // expected size of the start symbol under weights
+}
+
+// mean text size of values drawn from a fixed seed, so tuning is reproducible
+fn mean_len(mut gen: impl FnMut(&mut StdRng) -> String) -> f64 {
+    let mut rng = StdRng::seed_from_u64(0);
+    let total: usize = (0..SAMPLES).map(|_| gen(&mut rng).len()).sum();
+    total as f64 / SAMPLES as f64
+}
+
+impl Boltzmann {
+    pub fn tune(
+        rules: &Rules,
+        leaves: &Leaves,
+        metric: SizeMetric,
+        bnf: &str,
+        target: f64,
+    ) -> Result<Self, String> {
+        if !rules.contains_key(bnf) {
+            return Err(format!("No production rule for {}", bnf));
+        }
+        if !analysis::productive(rules).contains(bnf) {
+            return Err(format!("{} derives no finite text", bnf));
+        }
+        if let Some(b) = Self::search(rules, leaves, metric, bnf, target, &|_, _| true) {
+            return Ok(b);
+        }
+        // a cycle that does not grow gives infinitely many derivations of one size
+        // for every x, leave the alternatives closing such cycles out
+        let closing = zero_cycles(rules, leaves, metric, bnf);
+        let keep = |k: &str, i: usize| !closing.contains(&(k.to_string(), i));
+        Self::search(rules, leaves, metric, bnf, target, &keep).ok_or_else(|| {
+            format!(
+                "{} has infinitely many derivations of the same size, some rule derives itself without growing",
+                bnf
+            )
+        })
+    }
+
+    // x for the target size over the alternatives accepted by keep, None when the
+    // system diverges for every x
+    fn search(
+        rules: &Rules,
+        leaves: &Leaves,
+        metric: SizeMetric,
+        bnf: &str,
+        target: f64,
+        keep: &dyn Fn(&str, usize) -> bool,
+    ) -> Option<Self> {
+        let productive = analysis::productive_by(rules, keep);
+        if !productive.contains(bnf) {
+            return None;
+        }
+        // unproductive alternatives never finish, leave them out of the system
+        let live = |k: &str, i: usize| {
+            keep(k, i) && nonterminals(&rules[k][i]).all(|t| productive.contains(t))
+        };
+        let system = System {
+            rules,
+            metric,
+            names: analysis::reachable(rules, bnf, live),
+            live: &live,
+            leaves,
+        };
+        let at = |x: f64| -> Option<Boltzmann> {
+            let weights = system.weights(x)?;
+            let expected = analysis::size_report(rules, metric, &weights, leaves).expected[bnf];
+            expected.is_finite().then_some(Boltzmann {
+                x,
+                weights,
+                expected,
+            })
+        };
+        // start near zero; long fixed texts (uuids, dates, hex strings) underflow
+        // there, so step up until the values are representable
+        let mut lo = [1e-9, 1e-6, 1e-3, 1e-2, 1e-1, 0.5, 0.9, 0.99, 0.999]
+            .into_iter()
+            .find_map(at)?;
+        // grow x until the size overshoots or the series diverges
+        let mut hi = 1.0;
+        while let Some(b) = at(hi) {
+            if b.expected >= target || hi > 1e9 {
+                break;
+            }
+            lo = b;
+            hi *= 2.0;
+        }
+        for _ in 0..100 {
+            if lo.expected >= target || hi - lo.x <= lo.x * 1e-12 {
+                break;
+            }
+            let mid = (lo.x + hi) / 2.0;
+            match at(mid) {
+                Some(b) if b.expected <= target => lo = b,
+                _ => hi = mid,
+            }
+        }
+        Some(lo)
+    }
+}
+
+// alternatives closing a cycle of nonterminals reachable from bnf along which
+// nothing grows: an alternative adding no size whose other nonterminals may be
+// empty. A depth first search from bnf takes the ones leading back onto its stack,
+// so every rule keeps its way down to bnf's finite texts.
+fn zero_cycles(
+    rules: &Rules,
+    leaves: &Leaves,
+    metric: SizeMetric,
+    bnf: &str,
+) -> HashSet<(String, usize)> {
+    let mut empty = HashSet::new();
+    loop {
+        let before = empty.len();
+        for (k, alts) in rules {
+            let may_be_empty = match leaves.get(k) {
+                Some(&l) => l == 0.0,
+                None => alts.iter().any(|a| {
+                    metric.base(a) == 0 && nonterminals(a).all(|t| empty.contains(t.as_str()))
+                }),
+            };
+            if may_be_empty {
+                empty.insert(k.as_str());
+            }
+        }
+        if empty.len() == before {
+            break;
+        }
+    }
+    // k -> (alternative, nonterminal it may be reached through without growing)
+    let mut edges: HashMap<&str, Vec<(usize, &str)>> = HashMap::new();
+    for (k, alts) in rules.iter().filter(|(k, _)| !leaves.contains_key(*k)) {
+        for (i, alt) in alts.iter().enumerate().filter(|(_, a)| metric.base(a) == 0) {
+            let children: Vec<&String> = nonterminals(alt).collect();
+            for (p, t) in children.iter().enumerate() {
+                let rest_empty = (0..children.len())
+                    .filter(|&q| q != p)
+                    .all(|q| empty.contains(children[q].as_str()));
+                if rest_empty {
+                    edges.entry(k).or_default().push((i, t));
+                }
+            }
+        }
+    }
+    let mut closing = HashSet::new();
+    let mut seen = HashSet::from([bnf]);
+    let mut frames = vec![(bnf, 0)];
+    while let Some((k, next)) = frames.last_mut() {
+        let k = *k;
+        match edges.get(k).and_then(|e| e.get(*next)) {
+            Some(&(i, t)) => {
+                *next += 1;
+                if frames.iter().any(|(f, _)| *f == t) {
+                    closing.insert((k.to_string(), i));
+                } else if seen.insert(t) {
+                    frames.push((t, 0));
+                }
+            }
+            None => {
+                frames.pop();
+            }
+        }
+    }
+    closing
+}
+
+// generating function system over the nonterminals reachable from the start
+struct System<'a> {
+    rules: &'a Rules,
+    metric: SizeMetric,
+    names: Vec<String>,
+    live: &'a dyn Fn(&str, usize) -> bool,
+    leaves: &'a Leaves,
+}
+
+impl System<'_> {
+    // values and jacobian of the right hand sides at y
+    fn eval(&self, x: f64, y: &[f64]) -> (Vec<f64>, Vec<Vec<f64>>) {
+        let idx: HashMap<&String, usize> =
+            self.names.iter().enumerate().map(|(i, k)| (k, i)).collect();
+        let n = self.names.len();
+        let mut f = vec![0.0; n];
+        let mut j = vec![vec![0.0; n]; n];
+        for (i, k) in self.names.iter().enumerate() {
+            if let Some(&l) = self.leaves.get(k) {
+                f[i] = x.powf(l);
+                continue;
+            }
+            for (a, alt) in self.rules[k].iter().enumerate() {
+                if !(self.live)(k, a) {
+                    continue;
+                }
+                let xs = x.powi(self.metric.base(alt) as i32);
+                let children: Vec<usize> = nonterminals(alt).map(|t| idx[t]).collect();
+                f[i] += children.iter().fold(xs, |acc, &c| acc * y[c]);
+                for (p, &c) in children.iter().enumerate() {
+                    j[i][c] += children
+                        .iter()
+                        .enumerate()
+                        .filter(|&(q, _)| q != p)
+                        .fold(xs, |acc, (_, &d)| acc * y[d]);
+                }
+            }
+        }
+        (f, j)
+    }
+
+    // least fixpoint y = f(y) by newton iteration from 0, None beyond the radius of convergence
+    fn values(&self, x: f64) -> Option<Vec<f64>> {
+        let n = self.names.len();
+        let mut y = vec![0.0; n];
+        for _ in 0..200 {
+            let (f, j) = self.eval(x, &y);
+            let a = (0..n)
+                .map(|r| (0..n).map(|c| (r == c) as u8 as f64 - j[r][c]).collect())
+                .collect();
+            let rhs: Vec<f64> = f.iter().zip(&y).map(|(f, y)| f - y).collect();
+            let delta = solve(a, rhs)?;
+            let mut done = true;
+            for (yi, d) in y.iter_mut().zip(&delta) {
+                *yi += d;
+                done &= d.abs() <= 1e-10 * yi.abs();
+            }
+            if y.iter().any(|v| !v.is_finite() || *v < 0.0 || *v > 1e150) {
+                return None;
+            }
+            if done {
+                // the least fixpoint is the one where the jacobian is subcritical
+                return subcritical(&self.eval(x, &y).1).then_some(y);
+            }
+        }
+        None
+    }
+
+    fn weights(&self, x: f64) -> Option<Weights> {
+        let y = self.values(x)?;
+        let idx: HashMap<&String, usize> =
+            self.names.iter().enumerate().map(|(i, k)| (k, i)).collect();
+        Some(
+            self.names
+                .iter()
+                .map(|k| {
+                    if self.leaves.contains_key(k) {
+                        return (k.clone(), vec![1.0]);
+                    }
+                    let w = self.rules[k]
+                        .iter()
+                        .enumerate()
+                        .map(|(a, alt)| match (self.live)(k, a) {
+                            true => {
+                                nonterminals(alt)
+                                    .fold(x.powi(self.metric.base(alt) as i32), |acc, t| {
+                                        acc * y[idx[t]]
+                                    })
+                                    / y[idx[k]]
+                            }
+                            false => 0.0,
+                        })
+                        .collect();
+                    (k.clone(), w)
+                })
+                .collect(),
+        )
+    }
+}
+
+impl Collection {
+    // expected length of the text generated by gen, default 50
+    pub fn set_expected_size(&mut self, size: f64) {
+        self.expected_size = size;
+        self.tuned.write().unwrap().clear();
+    }
+
+    // branch probabilities gen uses for bnf, tuned on first use
+    pub fn boltzmann(&self, bnf: &str) -> Result<Arc<Boltzmann>, String> {
+        if let Some(b) = self.tuned.read().unwrap().get(bnf) {
+            return Ok(b.clone());
+        }
+        let rules = self.gen_rules(bnf)?;
+        let b = Arc::new(Boltzmann::tune(
+            &rules,
+            &self.leaves(&rules),
+            SizeMetric::Text,
+            bnf,
+            self.expected_size,
+        )?);
+        self.tuned
+            .write()
+            .unwrap()
+            .insert(bnf.to_string(), b.clone());
+        Ok(b)
+    }
+
+    // expected sizes under the probabilities gen uses for bnf
+    pub fn gen_size_report(&self, bnf: &str) -> Result<SizeReport, String> {
+        let rules = self.gen_rules(bnf)?;
+        let weights = &self.boltzmann(bnf)?.weights;
+        Ok(analysis::size_report(
+            &rules,
+            SizeMetric::Text,
+            weights,
+            &self.leaves(&rules),
+        ))
+    }
+
+    // expected sizes of the leaves among the generation rules, a regex exactly and
+    // a built-in or registered function from samples
+    fn leaves(&self, rules: &Rules) -> Leaves {
+        let mut leaves = Leaves::new();
+        let bindings = HashMap::new();
+        for (key, f) in self.fns.iter().filter(|(k, _)| rules.contains_key(*k)) {
+            let size = mean_len(|rng| {
+                f(&mut GenContext {
+                    rng,
+                    depth: 1,
+                    bindings: &bindings,
+                })
+            });
+            leaves.insert(key.clone(), size);
+        }
+        for ast in self.h.values() {
+            for e0 in ast.expr0s() {
+                let key = match e0 {
+                    Expr0::Regex { regex } => regex.key(),
+                    Expr0::Builtin { builtin } => builtin.key(),
+                    Expr0::Computed { field } => field.key(),
+                    _ => continue,
+                };
+                if !rules.contains_key(&key) || leaves.contains_key(&key) {
+                    continue;
+                }
+                let size = match e0 {
+                    Expr0::Regex { regex } => regex.expected_len(),
+                    Expr0::Builtin { builtin } => mean_len(|rng| builtin.sample(rng, &self.lists)),
+                    Expr0::Computed { field } => field.expected_len(),
+                    _ => continue,
+                };
+                leaves.insert(key, size);
+            }
+        }
+        leaves
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tuned_expected_size() {
+        let mut c = Collection::new();
+        c.add(r#"<e>::=<e>"+"<e>|<e>"*"<e>|"-"<e>|<d>"#).unwrap();
+        c.add(r#"<d>::="0"|"1"|"2"|"3"|"4"|"5"|"6"|"7"|"8"|"9""#)
+            .unwrap();
+        for target in [5.0, 100.0, 2000.0] {
+            c.set_expected_size(target);
+            let b = c.boltzmann("<e>").unwrap();
+            assert!((b.expected - target).abs() < target * 1e-3);
+        }
+    }
+
+    #[test]
+    fn leaves_measured_like_tuned() {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+        let mut c = Collection::new();
+        c.add(r#"<l>::=<w>" "<l>|<w>"#).unwrap();
+        c.add("<w>::=/[a-z]+/|<@int(1,100000)>").unwrap();
+        let b = c.boltzmann("<l>").unwrap();
+        assert!((b.expected - 50.0).abs() < 0.1, "{}", b.expected);
+        let mut rng = StdRng::seed_from_u64(1);
+        let total: usize = (0..4000)
+            .map(|_| c.gen_with_rng("<l>", &mut rng).unwrap().len())
+            .sum();
+        let mean = total as f64 / 4000.0;
+        assert!((mean - 50.0).abs() < 2.5, "{}", mean);
+    }
+
+    #[test]
+    fn finite_language_saturates() {
+        let mut c = Collection::new();
+        c.add(r#"<a>::="x"|"yy""#).unwrap();
+        c.set_expected_size(10.0);
+        let b = c.boltzmann("<a>").unwrap();
+        assert!(b.expected < 2.0 && b.expected > 1.99);
+    }
+
+    #[test]
+    fn unit_cycle_left_out() {
+        let mut c = Collection::new();
+        c.add(r#"<a>::=<a>|"x""#).unwrap();
+        assert_eq!(c.gen("<a>").unwrap(), "x");
+        // the cycle through <b> is closed by <b>, <c> still reaches "z"
+        c.add(r#"<s>::=<b>"-"<s>|<b>"#).unwrap();
+        c.add("<b>::=<c>|<b><e>").unwrap();
+        c.add(r#"<c>::=<b>|"z""#).unwrap();
+        c.add(r#"<e>::=E|"y""#).unwrap();
+        let b = c.boltzmann("<s>").unwrap();
+        assert!((b.expected - 50.0).abs() < 0.1, "{}", b.expected);
+        assert!(c.gen("<s>").unwrap().split('-').all(|z| z == "z"));
+    }
+
+    #[test]
+    fn unreachable_rules_ignored() {
+        let mut c = Collection::new();
+        c.add(r#"<a>::="x"<a>|"x""#).unwrap();
+        c.add("<unused>::=<missing>").unwrap();
+        assert!(c.gen("<a>").is_ok());
+        assert_eq!(
+            c.gen("<unused>").unwrap_err(),
+            "No production rule for <missing>"
+        );
+    }
+}
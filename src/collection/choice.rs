@@ -0,0 +1,223 @@
+// where generation takes its decisions from
+//
+// gen_from_ast asks a Chooser which alternative to expand each time it expands a
+// rule, how often to repeat and which character to pick inside regex terminals,
+// and the values of built-ins. Sampling from an rng gives gen; reading a byte
+// stream lets coverage guided fuzzers (libFuzzer, cargo-fuzz) steer generation
+// with the bytes they mutate.
+use super::analysis::{min_alternatives, nonterminals, productive};
+use super::{Collection, Rules, Weights};
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+use std::collections::HashMap;
+
+pub trait Chooser {
+    // index among the n alternatives of rule
+    fn alternative(&mut self, rule: &str, n: usize) -> Result<usize, String>;
+    // count of a bounded repetition, in min..=max
+    fn repeat(&mut self, min: usize, max: usize) -> Result<usize, String>;
+    // index among n equally likely options
+    fn pick(&mut self, n: usize) -> Result<usize, String>;
+    // a generated value, gen draws whatever randomness it needs from the rng given
+    fn value(&mut self, gen: &mut dyn FnMut(&mut dyn RngCore) -> String) -> Result<String, String>;
+}
+
+// alternatives drawn with the given probabilities
+pub struct Sampler<'a, R: Rng + ?Sized> {
+    pub weights: &'a Weights,
+    pub rng: &'a mut R,
+}
+
+impl<R: Rng + ?Sized> Chooser for Sampler<'_, R> {
+    fn alternative(&mut self, rule: &str, _: usize) -> Result<usize, String> {
+        let probs = self
+            .weights
+            .get(rule)
+            .ok_or_else(|| format!("No branch probabilities for {}", rule))?;
+        let mut rnd = self.rng.gen::<f64>() * probs.iter().sum::<f64>();
+        Ok(probs
+            .iter()
+            .position(|p| {
+                rnd -= p;
+                rnd < 0.0
+            })
+            .or_else(|| probs.iter().rposition(|&p| p > 0.0))
+            .unwrap_or(0))
+    }
+
+    fn repeat(&mut self, min: usize, max: usize) -> Result<usize, String> {
+        Ok(self.rng.gen_range(min..=max))
+    }
+
+    fn pick(&mut self, n: usize) -> Result<usize, String> {
+        Ok(self.rng.gen_range(0..n))
+    }
+
+    fn value(&mut self, gen: &mut dyn FnMut(&mut dyn RngCore) -> String) -> Result<String, String> {
+        let mut rng = &mut *self.rng;
+        Ok(gen(&mut rng))
+    }
+}
+
+// a cursor over fuzzer bytes, like arbitrary::Unstructured; one input can feed
+// several samples, each taking the bytes it reads
+pub struct ByteStream<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteStream<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        ByteStream { data, pos: 0 }
+    }
+
+    // bytes not read yet
+    pub fn len(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let b = self.data.get(self.pos).copied();
+        self.pos += b.is_some() as usize;
+        b
+    }
+}
+
+// every choice reads one byte (two past 256 alternatives) and picks among the
+// alternatives that can finish; once the bytes run out it takes the alternative
+// of the shortest derivation, so the sample still completes
+pub(crate) struct ByteChooser<'s, 'a> {
+    stream: &'s mut ByteStream<'a>,
+    live: HashMap<String, Vec<usize>>,
+    shortest: HashMap<String, (usize, usize)>,
+}
+
+impl<'s, 'a> ByteChooser<'s, 'a> {
+    pub(crate) fn new(rules: &Rules, stream: &'s mut ByteStream<'a>) -> Self {
+        let productive = productive(rules);
+        let live = rules
+            .iter()
+            .map(|(k, alts)| {
+                let ok = (0..alts.len())
+                    .filter(|&i| nonterminals(&alts[i]).all(|t| productive.contains(t)))
+                    .collect();
+                (k.clone(), ok)
+            })
+            .collect();
+        ByteChooser {
+            stream,
+            live,
+            shortest: min_alternatives(rules),
+        }
+    }
+}
+
+impl Chooser for ByteChooser<'_, '_> {
+    fn alternative(&mut self, rule: &str, _: usize) -> Result<usize, String> {
+        let (live, shortest) = match (self.live.get(rule), self.shortest.get(rule)) {
+            (Some(l), Some(s)) => (l, s.1),
+            _ => return Err(format!("{} derives no finite text", rule)),
+        };
+        if live.len() < 2 {
+            return Ok(shortest);
+        }
+        let pick = match (live.len() > 256, self.stream.next()) {
+            (_, None) => return Ok(shortest),
+            (false, Some(b)) => b as usize,
+            (true, Some(b)) => (b as usize) << 8 | self.stream.next().unwrap_or(0) as usize,
+        };
+        Ok(live[pick % live.len()])
+    }
+
+    // the fewest repetitions once the bytes run out
+    fn repeat(&mut self, min: usize, max: usize) -> Result<usize, String> {
+        Ok(match self.stream.next() {
+            Some(b) => min + b as usize % (max - min + 1),
+            None => min,
+        })
+    }
+
+    fn pick(&mut self, n: usize) -> Result<usize, String> {
+        let pick = match (n > 256, self.stream.next()) {
+            (_, None) => return Ok(0),
+            (false, Some(b)) => b as usize,
+            (true, Some(b)) => (b as usize) << 8 | self.stream.next().unwrap_or(0) as usize,
+        };
+        Ok(pick % n)
+    }
+
+    // up to eight bytes seed the rng of the value
+    fn value(&mut self, gen: &mut dyn FnMut(&mut dyn RngCore) -> String) -> Result<String, String> {
+        let seed = (0..8)
+            .map_while(|_| self.stream.next())
+            .fold(0, |s, b| s << 8 | b as u64);
+        Ok(gen(&mut StdRng::seed_from_u64(seed)))
+    }
+}
+
+// arbitrary::Arbitrary style adapter for fuzz targets, the arbitrary crate itself
+// is not a dependency; in a cargo-fuzz target:
+//   fuzz_target!(|data: &[u8]| {
+//       let input = GRAMMAR.arbitrary("<output>").arbitrary_take_rest(data).unwrap();
+//       ...
+//   });
+pub struct Arbitrary<'c> {
+    collection: &'c Collection,
+    start: String,
+}
+
+impl Arbitrary<'_> {
+    // a sample from the front of the stream, the rest is left for further samples
+    pub fn arbitrary(&self, u: &mut ByteStream) -> Result<String, String> {
+        self.collection.gen_from_stream(&self.start, u)
+    }
+
+    pub fn arbitrary_take_rest(&self, data: &[u8]) -> Result<String, String> {
+        self.collection.gen_from_bytes(&self.start, data)
+    }
+}
+
+impl Collection {
+    pub fn arbitrary(&self, start: &str) -> Arbitrary<'_> {
+        Arbitrary {
+            collection: self,
+            start: start.to_string(),
+        }
+    }
+
+    pub fn gen_from_bytes(&self, start: &str, data: &[u8]) -> Result<String, String> {
+        self.gen_from_stream(start, &mut ByteStream::new(data))
+    }
+
+    pub fn gen_from_stream(&self, start: &str, stream: &mut ByteStream) -> Result<String, String> {
+        let rules = self.gen_rules(start)?;
+        self.gen_with(start, &mut ByteChooser::new(&rules, stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_steer_and_run_out() {
+        let mut c = Collection::new();
+        c.add(r#"<l>::=<d><l>|<d>"#).unwrap();
+        c.add(r#"<d>::="0"|"1"|"2""#).unwrap();
+        assert_eq!(c.gen_from_bytes("<l>", &[]).unwrap(), "0");
+        // continue, 2, continue, 1, stop, 0
+        assert_eq!(c.gen_from_bytes("<l>", &[0, 2, 2, 1, 1, 0]).unwrap(), "210");
+        // running out in the middle still finishes
+        assert_eq!(c.gen_from_bytes("<l>", &[0, 1, 0]).unwrap(), "100");
+        let mut u = ByteStream::new(&[1, 2, 1, 1]);
+        let a = c.arbitrary("<l>");
+        assert_eq!(a.arbitrary(&mut u).unwrap(), "2");
+        assert_eq!(u.len(), 2);
+        assert_eq!(a.arbitrary(&mut u).unwrap(), "1");
+        assert!(u.is_empty());
+    }
+}
@@ -0,0 +1,34 @@
+// filling in computed fields, see src/computed.rs
+use super::{Collection, Derivation};
+
+// expansions of item in tree, not looking into them
+fn count(tree: &Derivation, item: &str) -> usize {
+    match tree {
+        Derivation::Node { rule, .. } if rule == item => 1,
+        Derivation::Node { children, .. } => children.iter().map(|c| count(c, item)).sum(),
+        Derivation::Leaf(_) => 0,
+    }
+}
+
+impl Collection {
+    // the children of a finished expansion of rule are complete but for its fields
+    pub(super) fn fill_fields(
+        &self,
+        rule: &str,
+        alt: usize,
+        children: &mut [Derivation],
+        binary: bool,
+    ) -> Result<(), String> {
+        let ast = self
+            .h
+            .get(rule)
+            .ok_or_else(|| format!("No production rule for {}", rule))?;
+        let fields = ast.alternative_fields()?.swap_remove(alt);
+        for (i, field, target) in fields {
+            let target = &children[target];
+            let n = field.item().map_or(0, |item| count(target, item));
+            children[i] = Derivation::Leaf(field.value(&target.text(), n, binary)?);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+// derivation tree of a generated sample
+use super::{Collection, Sampler, SizeMetric, Weights};
+use rand::Rng;
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub enum Derivation {
+    Leaf(String),
+    Node {
+        rule: String, // "<name>" of the expanded nonterminal
+        alt: usize,   // index of the chosen alternative
+        children: Vec<Derivation>,
+    },
+}
+
+// trees are as deep as the text is long, so cloning and dropping keep their own
+// stack instead of recursing
+impl Clone for Derivation {
+    fn clone(&self) -> Self {
+        // nodes being copied as (node, its children copied so far)
+        let mut frames: Vec<(&Derivation, Vec<Derivation>)> = vec![(self, vec![])];
+        loop {
+            let (d, done) = frames.last_mut().unwrap();
+            let (rule, alt, children) = match d {
+                Derivation::Leaf(s) => return Derivation::Leaf(s.clone()),
+                Derivation::Node {
+                    rule,
+                    alt,
+                    children,
+                } => (rule, *alt, children),
+            };
+            match children.get(done.len()) {
+                Some(Derivation::Leaf(s)) => done.push(Derivation::Leaf(s.clone())),
+                Some(child) => frames.push((child, vec![])),
+                None => {
+                    let node = Derivation::Node {
+                        rule: rule.clone(),
+                        alt,
+                        children: frames.pop().unwrap().1,
+                    };
+                    match frames.last_mut() {
+                        Some((_, siblings)) => siblings.push(node),
+                        None => return node,
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Derivation {
+    fn drop(&mut self) {
+        if let Derivation::Node { children, .. } = self {
+            let mut todo = std::mem::take(children);
+            while let Some(mut d) = todo.pop() {
+                if let Derivation::Node { children, .. } = &mut d {
+                    todo.append(children);
+                }
+            }
+        }
+    }
+}
+
+impl Derivation {
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        self.write_text(&mut text);
+        text
+    }
+
+    pub fn size(&self, metric: SizeMetric) -> usize {
+        let mut size = 0;
+        let mut todo = vec![self];
+        while let Some(d) = todo.pop() {
+            match (d, metric) {
+                (Derivation::Leaf(s), SizeMetric::Text) => size += s.len(),
+                (Derivation::Leaf(_), SizeMetric::Nodes) => (),
+                (Derivation::Node { children, .. }, _) => {
+                    size += (metric == SizeMetric::Nodes) as usize;
+                    todo.extend(children);
+                }
+            }
+        }
+        size
+    }
+
+    // one line per level in the style of Ast::display, [number, child index of parent]label
+    pub fn display(&self) {
+        print!("{}", self.levels());
+    }
+
+    pub fn levels(&self) -> String {
+        let mut out = String::new();
+        let mut line: Vec<(usize, usize, &Derivation)> = vec![(0, 0, self)];
+        let mut level = 1;
+        while !line.is_empty() {
+            out += &format!("#{}  ", level);
+            let mut next = vec![];
+            for (nu, (pa, i, d)) in line.iter().enumerate() {
+                match d {
+                    Derivation::Leaf(s) => out += &format!("[{}, {} of {}]{:?}  ", nu, i, pa, s),
+                    Derivation::Node { rule, children, .. } => {
+                        out += &format!("[{}, {} of {}]{}  ", nu, i, pa, rule);
+                        next.extend(children.iter().enumerate().map(|(i, c)| (nu, i, c)));
+                    }
+                }
+            }
+            out.push('\n');
+            line = next;
+            level += 1;
+        }
+        out
+    }
+
+    // leaves left to right, with an explicit stack as trees can be as deep as the
+    // text is long
+    fn write_text(&self, text: &mut String) {
+        let mut todo = vec![self];
+        while let Some(d) = todo.pop() {
+            match d {
+                Derivation::Leaf(s) => *text += s,
+                Derivation::Node { children, .. } => todo.extend(children.iter().rev()),
+            }
+        }
+    }
+}
+
+impl Collection {
+    // like gen_weighted, but keeping the derivation tree
+    pub fn gen_tree<R: Rng + ?Sized>(
+        &self,
+        bnf: &str,
+        weights: &Weights,
+        rng: &mut R,
+    ) -> Result<Derivation, String> {
+        self.gen_derivation_with(bnf, &mut Sampler { weights, rng })
+    }
+}
@@ -0,0 +1,290 @@
+// Earley recognizer over the rules of a Collection
+//
+// Terminals are matched byte by byte, so a terminal "ab" is two scan steps.
+// Works for any context free grammar, including ambiguous and left recursive
+// ones; nullable nonterminals are handled by advancing over them at prediction
+// time (Aycock and Horspool).
+use super::Collection;
+use crate::parser::Symbol;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(super) enum Sym {
+    T(u8),
+    N(usize),
+}
+
+pub(super) struct Prod {
+    pub(super) lhs: usize,
+    pub(super) alt: usize,
+    pub(super) rhs: Vec<Sym>,
+    pub(super) syms: Vec<Symbol>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(super) struct Item {
+    pub(super) prod: usize,
+    pub(super) dot: usize,
+    pub(super) origin: usize,
+}
+
+pub struct Recognizer {
+    pub(super) names: Vec<String>,
+    pub(super) index: HashMap<String, usize>,
+    pub(super) prods: Vec<Prod>,
+    pub(super) by_lhs: Vec<Vec<usize>>,
+    pub(super) nullable: Vec<bool>,
+}
+
+// where and why an input is not in the language
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    pub offset: usize,     // bytes of input accepted as a valid prefix
+    pub found: Option<u8>, // None at end of input
+    pub expected: Vec<u8>, // bytes that could continue the prefix
+    pub end_allowed: bool, // the prefix itself is in the language
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.found {
+            Some(c) => write!(f, "unexpected {:?}", c as char)?,
+            None => write!(f, "unexpected end of input")?,
+        }
+        let mut expected: Vec<String> = self
+            .expected
+            .iter()
+            .map(|&c| format!("{:?}", c as char))
+            .collect();
+        if self.end_allowed {
+            expected.push("end of input".to_string());
+        }
+        if expected.is_empty() {
+            Ok(())
+        } else {
+            write!(f, ", expected {}", expected.join(" "))
+        }
+    }
+}
+
+impl Collection {
+    // compile the rules once, for checking many inputs
+    pub fn recognizer(&self) -> Result<Recognizer, String> {
+        let rules = self.rules()?;
+        let mut names: Vec<String> = rules.keys().cloned().collect();
+        names.sort();
+        let index: HashMap<String, usize> = names
+            .iter()
+            .enumerate()
+            .map(|(i, k)| (k.clone(), i))
+            .collect();
+        let mut prods = vec![];
+        let mut by_lhs = vec![vec![]; names.len()];
+        for (lhs, k) in names.iter().enumerate() {
+            for (alt, syms) in rules[k].iter().enumerate() {
+                let rhs = syms
+                    .iter()
+                    .flat_map(|s| match s {
+                        Symbol::Terminal(t) => t.bytes().map(Sym::T).collect(),
+                        Symbol::NonTerminal(t) => vec![Sym::N(index[t])],
+                    })
+                    .collect();
+                by_lhs[lhs].push(prods.len());
+                prods.push(Prod {
+                    lhs,
+                    alt,
+                    rhs,
+                    syms: syms.clone(),
+                });
+            }
+        }
+        let mut nullable = vec![false; names.len()];
+        loop {
+            let mut changed = false;
+            for p in &prods {
+                if !nullable[p.lhs] && p.rhs.iter().all(|s| matches!(s, Sym::N(n) if nullable[*n]))
+                {
+                    nullable[p.lhs] = true;
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        Ok(Recognizer {
+            names,
+            index,
+            prods,
+            by_lhs,
+            nullable,
+        })
+    }
+
+    pub fn accepts(&self, bnf: &str, input: &str) -> Result<bool, String> {
+        Ok(self.recognizer()?.check(bnf, input.as_bytes())?.is_none())
+    }
+}
+
+impl Recognizer {
+    // None when input is in the language of bnf
+    pub fn check(&self, bnf: &str, input: &[u8]) -> Result<Option<Mismatch>, String> {
+        let start = self.start(bnf)?;
+        let chart = self.chart(start, input);
+        let last = chart.len() - 1;
+        let end_allowed = self.complete(&chart[last], start);
+        if last == input.len() && end_allowed {
+            return Ok(None);
+        }
+        let mut expected: Vec<u8> = chart[last]
+            .iter()
+            .filter_map(|it| match self.prods[it.prod].rhs.get(it.dot) {
+                Some(Sym::T(c)) => Some(*c),
+                _ => None,
+            })
+            .collect::<HashSet<u8>>()
+            .into_iter()
+            .collect();
+        expected.sort();
+        Ok(Some(Mismatch {
+            offset: last,
+            found: input.get(last).copied(),
+            expected,
+            end_allowed,
+        }))
+    }
+
+    pub(super) fn start(&self, bnf: &str) -> Result<usize, String> {
+        self.index
+            .get(bnf)
+            .copied()
+            .ok_or_else(|| format!("No production rule for {}", bnf))
+    }
+
+    // whether set holds a finished derivation of start from position 0
+    pub(super) fn complete(&self, set: &[Item], start: usize) -> bool {
+        set.iter().any(|it| {
+            let p = &self.prods[it.prod];
+            it.origin == 0 && p.lhs == start && it.dot == p.rhs.len()
+        })
+    }
+
+    // item sets for every position up to the end of the longest parsable prefix
+    pub(super) fn chart(&self, start: usize, input: &[u8]) -> Vec<Vec<Item>> {
+        let mut chart: Vec<Vec<Item>> = vec![vec![]];
+        let mut seen: Vec<HashSet<Item>> = vec![HashSet::new()];
+        let add = |set: &mut Vec<Item>, seen: &mut HashSet<Item>, it: Item| {
+            if seen.insert(it) {
+                set.push(it);
+            }
+        };
+        for &p in &self.by_lhs[start] {
+            let it = Item {
+                prod: p,
+                dot: 0,
+                origin: 0,
+            };
+            add(&mut chart[0], &mut seen[0], it);
+        }
+        for i in 0..=input.len() {
+            let mut next = vec![];
+            let mut next_seen = HashSet::new();
+            let mut j = 0;
+            while j < chart[i].len() {
+                let it = chart[i][j];
+                j += 1;
+                let p = &self.prods[it.prod];
+                match p.rhs.get(it.dot) {
+                    Some(&Sym::N(b)) => {
+                        for &q in &self.by_lhs[b] {
+                            let new = Item {
+                                prod: q,
+                                dot: 0,
+                                origin: i,
+                            };
+                            add(&mut chart[i], &mut seen[i], new);
+                        }
+                        if self.nullable[b] {
+                            let new = Item {
+                                dot: it.dot + 1,
+                                ..it
+                            };
+                            add(&mut chart[i], &mut seen[i], new);
+                        }
+                    }
+                    Some(&Sym::T(c)) => {
+                        if input.get(i) == Some(&c) {
+                            let new = Item {
+                                dot: it.dot + 1,
+                                ..it
+                            };
+                            add(&mut next, &mut next_seen, new);
+                        }
+                    }
+                    None => {
+                        let mut k = 0;
+                        while k < chart[it.origin].len() {
+                            let w = chart[it.origin][k];
+                            k += 1;
+                            if self.prods[w.prod].rhs.get(w.dot) == Some(&Sym::N(p.lhs)) {
+                                let new = Item {
+                                    dot: w.dot + 1,
+                                    ..w
+                                };
+                                add(&mut chart[i], &mut seen[i], new);
+                            }
+                        }
+                    }
+                }
+            }
+            if i == input.len() || next.is_empty() {
+                break;
+            }
+            chart.push(next);
+            seen.push(next_seen);
+        }
+        chart
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collection(bnfs: &[&str]) -> Collection {
+        let mut c = Collection::new();
+        bnfs.iter().for_each(|b| c.add(b).unwrap());
+        c
+    }
+
+    #[test]
+    fn left_recursive_ambiguous() {
+        let c = collection(&[r#"<e>::=<e>"+"<e>|<e>"*"<e>|<n>"#, r#"<n>::="1"<n>|"1""#]);
+        assert!(c.accepts("<e>", "1+11*1").unwrap());
+        assert!(!c.accepts("<e>", "1+").unwrap());
+        assert!(!c.accepts("<e>", "").unwrap());
+    }
+
+    #[test]
+    fn nullable() {
+        let c = collection(&[r#"<s>::=<a><a>"x"<a>"#, r#"<a>::=E|"y""#]);
+        for ok in ["x", "yx", "yyx", "xy", "yxy"] {
+            assert!(c.accepts("<s>", ok).unwrap(), "{}", ok);
+        }
+        assert!(!c.accepts("<s>", "yyyx").unwrap());
+    }
+
+    #[test]
+    fn mismatch_position() {
+        let c = collection(&[r#"<s>::="ab"<s>|"c""#]);
+        let r = c.recognizer().unwrap();
+        let m = r.check("<s>", b"ababx").unwrap().unwrap();
+        assert_eq!(m.offset, 4);
+        assert_eq!(m.found, Some(b'x'));
+        assert_eq!(m.expected, b"ac".to_vec());
+        let m = r.check("<s>", b"abc?").unwrap().unwrap();
+        assert_eq!((m.offset, m.end_allowed), (3, true));
+        assert!(r.check("<s>", b"abc").unwrap().is_none());
+    }
+}
@@ -0,0 +1,399 @@
+// parse trees and shared packed parse forests, read back from the Earley chart
+//
+// The forest has one node per nonterminal and input span, holding every way
+// (packed alternative) the nonterminal derives that span. When every node has a
+// single way the input is unambiguous and the forest is returned as a plain
+// Derivation, the same shape generation produces.
+use super::earley::{Item, Recognizer, Sym};
+use super::{Collection, Derivation};
+use crate::parser::Symbol;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Parse {
+    Tree(Derivation),
+    Forest(Forest), // ambiguous input
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Forest {
+    pub nodes: Vec<ForestNode>,
+    pub root: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ForestNode {
+    pub rule: String,
+    pub start: usize,
+    pub end: usize,
+    pub packed: Vec<Packed>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Packed {
+    pub alt: usize,
+    pub children: Vec<ForestChild>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ForestChild {
+    Leaf(String),
+    Node(usize), // index into Forest::nodes
+}
+
+impl Parse {
+    // the derivation, or one of them when ambiguous
+    pub fn tree(&self) -> Derivation {
+        match self {
+            Parse::Tree(d) => d.clone(),
+            Parse::Forest(f) => f.trees(1).remove(0),
+        }
+    }
+}
+
+impl Forest {
+    // up to limit distinct derivations, leaving out those that derive a span from itself
+    pub fn trees(&self, limit: usize) -> Vec<Derivation> {
+        // nodes being enumerated, innermost last: node, packed alternative, child,
+        // the partial children lists of the alternative and the trees so far
+        struct Frame {
+            id: usize,
+            p: usize,
+            c: usize,
+            partial: Vec<Vec<Derivation>>,
+            out: Vec<Derivation>,
+        }
+        let mut path = HashSet::from([self.root]);
+        let mut frames = vec![Frame {
+            id: self.root,
+            p: 0,
+            c: 0,
+            partial: vec![vec![]],
+            out: vec![],
+        }];
+        // the trees of the child just finished
+        let mut options: Option<Vec<Derivation>> = None;
+        while let Some(f) = frames.last_mut() {
+            let node = &self.nodes[f.id];
+            if let Some(options) = options.take() {
+                f.partial = f
+                    .partial
+                    .iter()
+                    .flat_map(|pre| {
+                        options.iter().map(move |o| {
+                            let mut v = pre.clone();
+                            v.push(o.clone());
+                            v
+                        })
+                    })
+                    .take(limit)
+                    .collect();
+                f.c += 1;
+                continue;
+            }
+            if f.p == node.packed.len() || f.out.len() >= limit {
+                let mut f = frames.pop().unwrap();
+                f.out.truncate(limit);
+                path.remove(&f.id);
+                options = Some(f.out);
+                if frames.is_empty() {
+                    break;
+                }
+                continue;
+            }
+            let p = &node.packed[f.p];
+            match p.children.get(f.c) {
+                Some(ForestChild::Leaf(s)) => options = Some(vec![Derivation::Leaf(s.clone())]),
+                Some(ForestChild::Node(n)) if !path.insert(*n) => options = Some(vec![]),
+                Some(ForestChild::Node(n)) => frames.push(Frame {
+                    id: *n,
+                    p: 0,
+                    c: 0,
+                    partial: vec![vec![]],
+                    out: vec![],
+                }),
+                None => {
+                    let partial = std::mem::replace(&mut f.partial, vec![vec![]]);
+                    f.out
+                        .extend(partial.into_iter().map(|children| Derivation::Node {
+                            rule: node.rule.clone(),
+                            alt: p.alt,
+                            children,
+                        }));
+                    f.p += 1;
+                    f.c = 0;
+                }
+            }
+        }
+        options.unwrap_or_default()
+    }
+
+    pub fn display(&self) {
+        for (id, n) in self.nodes.iter().enumerate() {
+            let root = if id == self.root { " (root)" } else { "" };
+            println!("#{} {}[{}..{}]{}", id, n.rule, n.start, n.end, root);
+            for p in &n.packed {
+                let children: Vec<String> = p
+                    .children
+                    .iter()
+                    .map(|c| match c {
+                        ForestChild::Leaf(s) => format!("\"{}\"", s),
+                        ForestChild::Node(i) => format!("#{}", i),
+                    })
+                    .collect();
+                println!("  alternative {}: {}", p.alt, children.join(" "));
+            }
+        }
+    }
+
+    // the derivation when every node on the way has a single packed alternative
+    fn as_tree(&self) -> Option<Derivation> {
+        let mut path = HashSet::new();
+        // nodes being read back as (node, next child, children so far)
+        let mut frames: Vec<(usize, usize, Vec<Derivation>)> = vec![];
+        let mut next = Some(self.root);
+        loop {
+            if let Some(id) = next.take() {
+                if self.nodes[id].packed.len() != 1 || !path.insert(id) {
+                    return None;
+                }
+                frames.push((id, 0, vec![]));
+            }
+            let (id, c, children) = frames.last_mut()?;
+            let p = &self.nodes[*id].packed[0];
+            match p.children.get(*c) {
+                Some(ForestChild::Leaf(s)) => children.push(Derivation::Leaf(s.clone())),
+                Some(ForestChild::Node(n)) => next = Some(*n),
+                None => {
+                    let (id, _, children) = frames.pop().unwrap();
+                    path.remove(&id);
+                    let node = Derivation::Node {
+                        rule: self.nodes[id].rule.clone(),
+                        alt: p.alt,
+                        children,
+                    };
+                    match frames.last_mut() {
+                        Some((_, _, siblings)) => siblings.push(node),
+                        None => return Some(node),
+                    }
+                    continue;
+                }
+            }
+            if let Some((_, c, _)) = frames.last_mut() {
+                *c += 1;
+            }
+        }
+    }
+}
+
+struct Builder<'a> {
+    r: &'a Recognizer,
+    input: &'a [u8],
+    sets: Vec<HashSet<Item>>,
+    nodes: Vec<ForestNode>,
+    memo: HashMap<(usize, usize, usize), usize>,
+    splits: HashMap<(usize, usize, usize, usize), Vec<Vec<usize>>>,
+    todo: Vec<(usize, usize)>, // nodes still without packed alternatives, with their rule
+}
+
+impl Builder<'_> {
+    fn completed(&self, a: usize, i: usize, j: usize) -> bool {
+        if i == j {
+            return self.r.nullable[a];
+        }
+        self.r.by_lhs[a].iter().any(|&p| {
+            self.sets[j].contains(&Item {
+                prod: p,
+                dot: self.r.prods[p].rhs.len(),
+                origin: i,
+            })
+        })
+    }
+
+    // the node of a deriving input[i..j], its packed alternatives are filled in
+    // by build
+    fn node(&mut self, a: usize, i: usize, j: usize) -> usize {
+        if let Some(&id) = self.memo.get(&(a, i, j)) {
+            return id;
+        }
+        let id = self.nodes.len();
+        self.memo.insert((a, i, j), id);
+        self.nodes.push(ForestNode {
+            rule: self.r.names[a].clone(),
+            start: i,
+            end: j,
+            packed: vec![],
+        });
+        self.todo.push((id, a));
+        id
+    }
+
+    // fill in the nodes reachable from the root, with a worklist since the forest
+    // is as deep as the input is long
+    fn build(&mut self, a: usize, i: usize, j: usize) -> usize {
+        let root = self.node(a, i, j);
+        while let Some((id, a)) = self.todo.pop() {
+            let (i, j) = (self.nodes[id].start, self.nodes[id].end);
+            let mut packed = vec![];
+            for &p in &self.r.by_lhs[a] {
+                let prod = &self.r.prods[p];
+                for kids in self.split(p, i, prod.rhs.len(), j) {
+                    let mut kids = kids.into_iter();
+                    let children = prod
+                        .syms
+                        .iter()
+                        .map(|s| match s {
+                            Symbol::Terminal(t) => ForestChild::Leaf(t.clone()),
+                            Symbol::NonTerminal(_) => ForestChild::Node(kids.next().unwrap()),
+                        })
+                        .collect();
+                    packed.push(Packed {
+                        alt: prod.alt,
+                        children,
+                    });
+                }
+            }
+            self.nodes[id].packed = packed;
+        }
+        root
+    }
+
+    // the ways rhs[..k] of production p derives input[i..e], as forest nodes of its nonterminals
+    fn split(&mut self, p: usize, i: usize, k: usize, e: usize) -> Vec<Vec<usize>> {
+        if k == 0 {
+            return if e == i { vec![vec![]] } else { vec![] };
+        }
+        if let Some(v) = self.splits.get(&(p, i, k, e)) {
+            return v.clone();
+        }
+        let mut out = vec![];
+        match self.r.prods[p].rhs[k - 1] {
+            Sym::T(c) => {
+                if e > i && self.input[e - 1] == c {
+                    out = self.split(p, i, k - 1, e - 1);
+                }
+            }
+            Sym::N(b) => {
+                for s in i..=e {
+                    let prefix_empty = self.r.prods[p].rhs[..k - 1]
+                        .iter()
+                        .all(|x| matches!(x, Sym::N(n) if self.r.nullable[*n]));
+                    let prefix = (s == i && prefix_empty)
+                        || self.sets[s].contains(&Item {
+                            prod: p,
+                            dot: k - 1,
+                            origin: i,
+                        });
+                    if !prefix || !self.completed(b, s, e) {
+                        continue;
+                    }
+                    for mut pre in self.split(p, i, k - 1, s) {
+                        pre.push(self.node(b, s, e));
+                        out.push(pre);
+                    }
+                }
+            }
+        }
+        self.splits.insert((p, i, k, e), out.clone());
+        out
+    }
+}
+
+impl Recognizer {
+    pub fn parse(&self, bnf: &str, input: &[u8]) -> Result<Parse, String> {
+        if let Some(m) = self.check(bnf, input)? {
+            return Err(format!("{} at offset {}", m, m.offset));
+        }
+        let start = self.start(bnf)?;
+        let mut b = Builder {
+            r: self,
+            input,
+            sets: self
+                .chart(start, input)
+                .into_iter()
+                .map(|s| s.into_iter().collect())
+                .collect(),
+            nodes: vec![],
+            memo: HashMap::new(),
+            splits: HashMap::new(),
+            todo: vec![],
+        };
+        let root = b.build(start, 0, input.len());
+        let forest = Forest {
+            nodes: b.nodes,
+            root,
+        };
+        Ok(match forest.as_tree() {
+            Some(d) => Parse::Tree(d),
+            None => Parse::Forest(forest),
+        })
+    }
+}
+
+impl Collection {
+    pub fn parse(&self, bnf: &str, input: &str) -> Result<Parse, String> {
+        self.recognizer()?.parse(bnf, input.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collection::SizeMetric;
+
+    #[test]
+    fn unambiguous_tree() {
+        let mut c = Collection::new();
+        c.add(r#"<l>::=<l>"+"<d>|<d>"#).unwrap();
+        c.add(r#"<d>::="1"|"2"|E"#).unwrap();
+        let t = match c.parse("<l>", "1+2+").unwrap() {
+            Parse::Tree(t) => t,
+            Parse::Forest(_) => panic!("ambiguous"),
+        };
+        assert_eq!(t.text(), "1+2+");
+        // the same tree unranks back from its rank
+        let k = c.rank(&t).unwrap();
+        assert_eq!(c.unrank("<l>", 4, &k).unwrap(), t);
+    }
+
+    #[test]
+    fn ambiguous_forest() {
+        let mut c = Collection::new();
+        c.add(r#"<e>::=<e>"-"<e>|"1""#).unwrap();
+        let f = match c.parse("<e>", "1-1-1").unwrap() {
+            Parse::Forest(f) => f,
+            Parse::Tree(_) => panic!("not ambiguous"),
+        };
+        let trees = f.trees(10);
+        assert_eq!(trees.len(), 2);
+        assert_ne!(trees[0], trees[1]);
+        assert!(trees.iter().all(|t| t.text() == "1-1-1"));
+        assert!(c.parse("<e>", "1-").is_err());
+    }
+
+    #[test]
+    fn deep_input() {
+        let mut c = Collection::new();
+        c.add(r#"<l>::=<l>"0"|"1""#).unwrap();
+        let input = format!("1{}", "0".repeat(5000));
+        let t = c.parse("<l>", &input).unwrap().tree();
+        assert_eq!(t.size(SizeMetric::Nodes), 5001);
+        assert_eq!(t.text(), input);
+        c.add(r#"<l>::=<l>"0"|<l>"0"|"1""#).unwrap();
+        let input = &input[..500];
+        let f = match c.parse("<l>", input).unwrap() {
+            Parse::Forest(f) => f,
+            Parse::Tree(_) => panic!("not ambiguous"),
+        };
+        assert!(f.trees(3).iter().all(|t| t.text() == input));
+    }
+
+    #[test]
+    fn cyclic_grammar() {
+        let mut c = Collection::new();
+        c.add(r#"<a>::=<a>|<b>"#).unwrap();
+        c.add(r#"<b>::="x"|<a>"#).unwrap();
+        let p = c.parse("<a>", "x").unwrap();
+        assert_eq!(p.tree().text(), "x");
+    }
+}
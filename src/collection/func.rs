@@ -0,0 +1,110 @@
+// nonterminals generated by registered rust functions
+//
+// A registered function replaces the rule of its nonterminal in gen_from_ast. Its
+// output is recorded and replayed like a built-in value. A rule of the same name,
+// if the grammar has one, still describes the output to the recognizer and the
+// counts; without one the output may be any printable text. Tuning takes the
+// output as long as its mean over calls from a fixed seed, at depth 1 and without
+// bindings, whatever the rule says.
+use super::{Collection, Rules};
+use crate::parser::Symbol;
+use crate::regex::Regex;
+use rand::RngCore;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub struct GenContext<'a> {
+    pub rng: &'a mut dyn RngCore,
+    pub depth: usize, // rule expansions in progress, the start rule is 1
+    pub bindings: &'a HashMap<String, String>, // variables visible at the call
+}
+
+pub type GenFn = dyn Fn(&mut GenContext) -> String + Send + Sync;
+
+// what a function without a rule is taken to derive
+const ANY_TEXT: &str = r"(.|\s)*";
+
+impl Collection {
+    // name with or without the angle brackets
+    pub fn register_fn<F>(&mut self, name: &str, f: F)
+    where
+        F: Fn(&mut GenContext) -> String + Send + Sync + 'static,
+    {
+        let key = match name.starts_with('<') {
+            true => name.to_string(),
+            false => format!("<{}>", name),
+        };
+        self.fns.insert(key, Arc::new(f));
+        self.tuned.write().unwrap().clear();
+    }
+
+    // rules for the registered functions that have none
+    pub(super) fn fn_rules(&self, rules: &mut Rules) {
+        let any = Regex::new(ANY_TEXT).unwrap();
+        for key in self.fns.keys() {
+            if !rules.contains_key(key) {
+                rules.insert(key.clone(), vec![vec![Symbol::NonTerminal(any.key())]]);
+                rules.extend(any.rules());
+            }
+        }
+    }
+
+    // every registered function as a leaf, its rule is never expanded by gen
+    pub(super) fn fn_leaves(&self, rules: &mut Rules) {
+        for key in self.fns.keys() {
+            rules.insert(key.clone(), vec![vec![]]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn registered_fn_replaces_rule() {
+        let mut c = Collection::new();
+        c.add(r#"<list>::=<item>" "<list>|<item>"#).unwrap();
+        c.add(r#"<item>::=<word>"/"<tag>"#).unwrap();
+        c.add(r#"<tag>::="a"|"b""#).unwrap();
+        c.register_fn("word", |ctx| {
+            format!("w{}d{}", ctx.rng.gen_range(0..10), ctx.depth)
+        });
+        c.register_fn("<tag>", |_| "c".to_string());
+        let mut rng = StdRng::seed_from_u64(2);
+        for _ in 0..20 {
+            let (text, choices) = c.gen_recorded("<list>", &mut rng).unwrap();
+            // the k-th item is in the k-th nested <list>
+            for (k, item) in text.split(' ').enumerate() {
+                let (word, tag) = item.split_once('/').unwrap();
+                assert!(word.starts_with('w') && word.ends_with(&format!("d{}", k + 2)));
+                assert_eq!(tag, "c");
+            }
+            assert_eq!(c.replay("<list>", &choices).unwrap(), text);
+        }
+        // <word> has no rule and derives any text, <tag> keeps its rule
+        assert!(c.accepts("<list>", "x y/a").unwrap());
+        assert!(!c.accepts("<list>", "w1d3/c").unwrap());
+    }
+
+    #[test]
+    fn tuned_around_fn() {
+        let mut c = Collection::new();
+        c.add(r#"<list>::=<word>" "<list>|<word>"#).unwrap();
+        c.register_fn("word", |ctx| format!("w{}", ctx.rng.gen_range(0..10)));
+        c.register_fn("tag", |ctx| "t".repeat(ctx.rng.gen_range(0..=8)));
+        c.add(r#"<tagged>::=<tag>"+"<tagged>|<tag>"#).unwrap();
+        let mut rng = StdRng::seed_from_u64(3);
+        for bnf in ["<list>", "<tagged>"] {
+            let b = c.boltzmann(bnf).unwrap();
+            assert!((b.expected - 50.0).abs() < 0.1, "{}", b.expected);
+            let total: usize = (0..4000)
+                .map(|_| c.gen_with_rng(bnf, &mut rng).unwrap().len())
+                .sum();
+            let mean = total as f64 / 4000.0;
+            assert!((mean - 50.0).abs() < 2.5, "{} {}", bnf, mean);
+        }
+    }
+}
@@ -0,0 +1,353 @@
+// structure aware mutation of derivation trees, in the style of Nautilus
+//
+// Every mutation swaps one subtree for another derivation of the same nonterminal,
+// so the result stays in the language of the start symbol:
+//   replace  draws a fresh subtree of a grammar rule the way gen does
+//   recurse  nests a subtree in copies of an ancestor of the same nonterminal
+//   splice   takes a subtree of the same nonterminal from another corpus entry
+// The computed fields of the rules above a swapped subtree are filled in again.
+use super::{Boltzmann, Collection, Derivation, Recognizer, Sampler};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const MAX_RECURSION: usize = 8; // copies added by one recurse mutation, at most
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mutation {
+    Replace,
+    Recurse,
+    Splice,
+}
+
+pub struct Mutator<'a> {
+    collection: &'a Collection,
+    bnf: String,
+    boltzmann: Arc<Boltzmann>,
+    recognizer: Recognizer,
+    corpus: Vec<Derivation>,
+    subtrees: HashMap<String, Vec<Derivation>>, // "<name>" -> its subtrees in the corpus
+}
+
+impl Collection {
+    pub fn mutator(&self, bnf: &str) -> Result<Mutator<'_>, String> {
+        Ok(Mutator {
+            collection: self,
+            bnf: bnf.to_string(),
+            boltzmann: self.boltzmann(bnf)?,
+            recognizer: self.recognizer()?,
+            corpus: vec![],
+            subtrees: HashMap::new(),
+        })
+    }
+}
+
+impl Mutator<'_> {
+    // parse a seed input and add its derivation to the corpus
+    pub fn add_input(&mut self, input: &[u8]) -> Result<&Derivation, String> {
+        let tree = self.recognizer.parse(&self.bnf, input)?.tree();
+        self.add_tree(tree)
+    }
+
+    pub fn add_tree(&mut self, tree: Derivation) -> Result<&Derivation, String> {
+        match &tree {
+            Derivation::Node { rule, .. } if *rule == self.bnf => (),
+            _ => return Err(format!("not a derivation of {}", self.bnf)),
+        }
+        for (_, node) in nodes(&tree) {
+            if let Derivation::Node { rule, .. } = node {
+                let same = self.subtrees.entry(rule.clone()).or_default();
+                if !same.contains(node) {
+                    same.push(node.clone());
+                }
+            }
+        }
+        self.corpus.push(tree);
+        Ok(self.corpus.last().unwrap())
+    }
+
+    pub fn corpus(&self) -> &[Derivation] {
+        &self.corpus
+    }
+
+    // a random mutation of tree, trying the kinds in random order until one applies
+    pub fn mutate<R: Rng + ?Sized>(
+        &self,
+        tree: &Derivation,
+        rng: &mut R,
+    ) -> Result<(Mutation, Derivation), String> {
+        let mut kinds = [Mutation::Replace, Mutation::Recurse, Mutation::Splice];
+        kinds.shuffle(rng);
+        for kind in kinds {
+            if let Some(t) = self.apply(kind, tree, rng)? {
+                return Ok((kind, t));
+            }
+        }
+        Err(format!("no mutation applies to {:?}", tree.text()))
+    }
+
+    // None when tree has no place for this kind of mutation
+    pub fn apply<R: Rng + ?Sized>(
+        &self,
+        kind: Mutation,
+        tree: &Derivation,
+        rng: &mut R,
+    ) -> Result<Option<Derivation>, String> {
+        let all = nodes(tree);
+        let c = self.collection;
+        match kind {
+            Mutation::Replace => {
+                // regexes, built-ins and the like are parsed into nodes of their
+                // automaton, only rules of the grammar are generated afresh
+                let places: Vec<usize> = (0..all.len())
+                    .filter(|&i| {
+                        let r = rule(all[i].1);
+                        c.h.contains_key(r) || c.fns.contains_key(r)
+                    })
+                    .collect();
+                let place = match places.choose(rng) {
+                    Some(&p) => p,
+                    None => return Ok(None),
+                };
+                let weights = &self.boltzmann.weights;
+                let fresh =
+                    c.gen_derivation_with(rule(all[place].1), &mut Sampler { weights, rng })?;
+                Ok(Some(replaced(c, tree, &path(&all, place), fresh)?))
+            }
+            Mutation::Recurse => {
+                // pairs of a node and an ancestor of the same nonterminal, counted
+                // per node through the nearest such ancestor
+                let (up, same) = same_ancestors(&all);
+                let total: usize = same.iter().sum();
+                if total == 0 {
+                    return Ok(None);
+                }
+                let mut k = rng.gen_range(0..total);
+                let mut inner = 0;
+                while k >= same[inner] {
+                    k -= same[inner];
+                    inner += 1;
+                }
+                let mut outer = up[inner].unwrap();
+                for _ in 0..k {
+                    outer = up[outer].unwrap();
+                }
+                let (outer, inner) = (path(&all, outer), path(&all, inner));
+                let below = &inner[outer.len()..];
+                let copy = at(tree, &outer);
+                let mut nested = copy.clone();
+                for _ in 0..rng.gen_range(1..=MAX_RECURSION) {
+                    nested = replaced(c, copy, below, nested)?;
+                }
+                Ok(Some(replaced(c, tree, &outer, nested)?))
+            }
+            Mutation::Splice => {
+                let places: Vec<(usize, Vec<&Derivation>)> = all
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, (_, node))| {
+                        let others: Vec<&Derivation> = self
+                            .subtrees
+                            .get(rule(node))?
+                            .iter()
+                            .filter(|d| d != node)
+                            .collect();
+                        (!others.is_empty()).then_some((i, others))
+                    })
+                    .collect();
+                let (place, others) = match places.choose(rng) {
+                    Some(p) => p,
+                    None => return Ok(None),
+                };
+                let donor = *others.choose(rng).unwrap();
+                Ok(Some(replaced(c, tree, &path(&all, *place), donor.clone())?))
+            }
+        }
+    }
+}
+
+fn rule(node: &Derivation) -> &str {
+    match node {
+        Derivation::Node { rule, .. } => rule,
+        Derivation::Leaf(_) => "",
+    }
+}
+
+// every inner node in preorder, with the position of its parent in the list and its
+// index among the children of the parent
+pub(crate) type Nodes<'a> = Vec<(Option<(usize, usize)>, &'a Derivation)>;
+
+pub(crate) fn nodes(tree: &Derivation) -> Nodes<'_> {
+    let mut out = vec![];
+    let mut stack = vec![(None, tree)];
+    while let Some((parent, node)) = stack.pop() {
+        if let Derivation::Node { children, .. } = node {
+            let me = out.len();
+            stack.extend(
+                children
+                    .iter()
+                    .enumerate()
+                    .rev()
+                    .map(|(i, c)| (Some((me, i)), c)),
+            );
+            out.push((parent, node));
+        }
+    }
+    out
+}
+
+// child indexes from the root to the node at position i of all
+pub(crate) fn path(all: &Nodes, mut i: usize) -> Vec<usize> {
+    let mut path = vec![];
+    while let Some((parent, j)) = all[i].0 {
+        path.push(j);
+        i = parent;
+    }
+    path.reverse();
+    path
+}
+
+// for each node the position of its nearest ancestor of the same nonterminal and the
+// number of them
+fn same_ancestors(all: &Nodes) -> (Vec<Option<usize>>, Vec<usize>) {
+    let mut up = vec![None; all.len()];
+    let mut same = vec![0; all.len()];
+    // ancestors of the node visited, and those of each nonterminal
+    let mut chain: Vec<usize> = vec![];
+    let mut by_rule: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, (parent, node)) in all.iter().enumerate() {
+        while chain.last().copied() != parent.map(|(p, _)| p) {
+            let gone = chain.pop().unwrap();
+            by_rule.get_mut(rule(all[gone].1)).unwrap().pop();
+        }
+        let above = by_rule.entry(rule(node)).or_default();
+        up[i] = above.last().copied();
+        same[i] = above.len();
+        above.push(i);
+        chain.push(i);
+    }
+    (up, same)
+}
+
+pub(crate) fn at<'a>(tree: &'a Derivation, path: &[usize]) -> &'a Derivation {
+    path.iter().fold(tree, |node, &i| match node {
+        Derivation::Node { children, .. } => &children[i],
+        Derivation::Leaf(_) => unreachable!("paths lead through nodes"),
+    })
+}
+
+// tree with the subtree at path swapped for new, rebuilt bottom up; the computed
+// fields of the rules above it are filled in again
+pub(crate) fn replaced(
+    c: &Collection,
+    tree: &Derivation,
+    path: &[usize],
+    mut new: Derivation,
+) -> Result<Derivation, String> {
+    let mut above = vec![];
+    let mut node = tree;
+    for &i in path {
+        above.push((node, i));
+        node = at(node, &[i]);
+    }
+    for (node, i) in above.into_iter().rev() {
+        let (rule, alt, children) = match node {
+            Derivation::Node {
+                rule,
+                alt,
+                children,
+            } => (rule, *alt, children),
+            Derivation::Leaf(_) => unreachable!("paths lead through nodes"),
+        };
+        let mut slot = Some(new);
+        let mut children: Vec<Derivation> = children
+            .iter()
+            .enumerate()
+            .map(|(j, child)| match j == i {
+                true => slot.take().unwrap(),
+                false => child.clone(),
+            })
+            .collect();
+        if c.fields.contains(rule) {
+            c.fill_fields(rule, alt, &mut children, c.binary)?;
+        }
+        new = Derivation::Node {
+            rule: rule.clone(),
+            alt,
+            children,
+        };
+    }
+    Ok(new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn mutants_stay_valid() {
+        let mut c = Collection::new();
+        c.add(r#"<e>::=<e>"+"<t>|<t>"#).unwrap();
+        c.add(r#"<t>::="/"<e>"/"|"1"|"2""#).unwrap();
+        let mut m = c.mutator("<e>").unwrap();
+        let seed = m.add_input(b"/1+2/+1").unwrap().clone();
+        m.add_input(b"2+/2/").unwrap();
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut seen = vec![];
+        for _ in 0..300 {
+            let (kind, t) = m.mutate(&seed, &mut rng).unwrap();
+            assert!(c.accepts("<e>", &t.text()).unwrap(), "{}", t.text());
+            if kind == Mutation::Recurse {
+                assert!(t.text().len() > seed.text().len());
+            }
+            if !seen.contains(&kind) {
+                seen.push(kind);
+            }
+        }
+        assert_eq!(seen.len(), 3);
+    }
+
+    #[test]
+    fn replace_generates_like_gen() {
+        let mut c = Collection::new();
+        c.add(r#"<list>::=<word>" "<list>|<word>"#).unwrap();
+        c.register_fn("word", |ctx| format!("w{}", ctx.rng.gen_range(0..10)));
+        let mut m = c.mutator("<list>").unwrap();
+        let seed = m.add_input(b"w1 w2").unwrap().clone();
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..100 {
+            let t = m
+                .apply(Mutation::Replace, &seed, &mut rng)
+                .unwrap()
+                .unwrap();
+            for word in t.text().split(' ') {
+                assert!(word.len() == 2 && word.starts_with('w'), "{:?}", t.text());
+            }
+        }
+    }
+
+    #[test]
+    fn fields_follow_mutants() {
+        let mut c = Collection::new();
+        c.add(r#"<msg>::=<@len(<body>)>"-"<body>"-"<@crc32(<body>)>"#)
+            .unwrap();
+        c.add(r#"<body>::=<word>" "<body>|<word>"#).unwrap();
+        c.add("<word>::=/[a-z]{1,5}/").unwrap();
+        let mut m = c.mutator("<msg>").unwrap();
+        let seed = m.add_input(b"5-ab cd-cf1d4dad").unwrap().clone();
+        m.add_input(b"1-x-8cdc1683").unwrap();
+        let mut rng = StdRng::seed_from_u64(5);
+        for _ in 0..100 {
+            let (_, t) = m.mutate(&seed, &mut rng).unwrap();
+            let text = t.text();
+            let mut parts = text.split('-');
+            let (len, body, crc) = (parts.next(), parts.next().unwrap(), parts.next());
+            assert_eq!(len, Some(body.len().to_string().as_str()), "{}", text);
+            let sum = crate::computed::crc32(body.as_bytes());
+            assert_eq!(crc, Some(format!("{:08x}", sum).as_str()), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,306 @@
+// near miss negative samples
+//
+// A valid derivation is generated and then broken by one edit of an error model:
+// a terminal dropped, duplicated or substituted by another terminal of the grammar,
+// two siblings swapped, or the text truncated. The recognizer confirms the result
+// is outside the language; edits that happen to stay inside it are retried.
+use super::{Boltzmann, Collection, Derivation, Recognizer, Sampler};
+use crate::parser::Symbol;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+const ATTEMPTS: usize = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    Drop,
+    Duplicate,
+    Swap,
+    Substitute,
+    Truncate,
+}
+
+pub const ALL_ERRORS: [ErrorKind; 5] = [
+    ErrorKind::Drop,
+    ErrorKind::Duplicate,
+    ErrorKind::Swap,
+    ErrorKind::Substitute,
+    ErrorKind::Truncate,
+];
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            ErrorKind::Drop => "drop",
+            ErrorKind::Duplicate => "duplicate",
+            ErrorKind::Swap => "swap",
+            ErrorKind::Substitute => "substitute",
+            ErrorKind::Truncate => "truncate",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for ErrorKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        ALL_ERRORS
+            .into_iter()
+            .find(|k| k.to_string() == s)
+            .ok_or_else(|| format!("unknown error kind {}", s))
+    }
+}
+
+// an invalid sample and how it was made
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NearMiss {
+    pub text: String,
+    pub kind: ErrorKind,
+    pub label: String, // the edit, e.g. drop "+" at 3
+    pub valid: String, // the text before the edit
+}
+
+pub struct NegativeSampler<'a> {
+    collection: &'a Collection,
+    bnf: String,
+    boltzmann: Arc<Boltzmann>,
+    recognizer: Recognizer,
+    terminals: Vec<String>,
+}
+
+type Span = (usize, usize);
+
+// spans of the non-empty terminals, and of the children of nodes with several; with
+// an explicit stack, trees are as deep as the text is long
+fn spans(
+    tree: &Derivation,
+    at: usize,
+    leaves: &mut Vec<Span>,
+    groups: &mut Vec<Vec<Span>>,
+) -> usize {
+    // nodes being walked as (children left, spans of the children so far, start),
+    // below one holding tree alone
+    let mut frames = vec![(std::slice::from_ref(tree).iter(), vec![], at)];
+    let mut end = at;
+    loop {
+        let (children, group, _) = frames.last_mut().unwrap();
+        match children.next() {
+            Some(Derivation::Leaf(s)) => {
+                if !s.is_empty() {
+                    leaves.push((end, end + s.len()));
+                    group.push((end, end + s.len()));
+                }
+                end += s.len();
+            }
+            Some(Derivation::Node { children, .. }) => frames.push((children.iter(), vec![], end)),
+            None => {
+                let (_, group, start) = frames.pop().unwrap();
+                if group.len() > 1 {
+                    groups.push(group);
+                }
+                match frames.last_mut() {
+                    Some((_, siblings, _)) if end > start => siblings.push((start, end)),
+                    Some(_) => (),
+                    None => return end,
+                }
+            }
+        }
+    }
+}
+
+impl Collection {
+    pub fn negative_sampler(&self, bnf: &str) -> Result<NegativeSampler<'_>, String> {
+        let rules = self.rules()?;
+        let mut terminals: Vec<String> = rules
+            .values()
+            .flatten()
+            .flatten()
+            .filter_map(|s| match s {
+                Symbol::Terminal(t) if !t.is_empty() => Some(t.clone()),
+                _ => None,
+            })
+            .collect();
+        terminals.sort();
+        terminals.dedup();
+        Ok(NegativeSampler {
+            collection: self,
+            bnf: bnf.to_string(),
+            boltzmann: self.boltzmann(bnf)?,
+            recognizer: self.recognizer()?,
+            terminals,
+        })
+    }
+}
+
+impl NegativeSampler<'_> {
+    // a confirmed invalid sample made with one of kinds
+    pub fn sample<R: Rng + ?Sized>(
+        &self,
+        kinds: &[ErrorKind],
+        rng: &mut R,
+    ) -> Result<NearMiss, String> {
+        if kinds.is_empty() {
+            return Err("no error kinds to apply".to_string());
+        }
+        for _ in 0..ATTEMPTS {
+            let weights = &self.boltzmann.weights;
+            let tree = self
+                .collection
+                .gen_derivation_with(&self.bnf, &mut Sampler { weights, rng })?;
+            let kind = *kinds.choose(rng).unwrap();
+            if let Some((text, label)) = self.apply(kind, &tree, rng) {
+                if self.recognizer.check(&self.bnf, text.as_bytes())?.is_some() {
+                    return Ok(NearMiss {
+                        text,
+                        kind,
+                        label,
+                        valid: tree.text(),
+                    });
+                }
+            }
+        }
+        Err(format!(
+            "no invalid sample of {} in {} attempts",
+            self.bnf, ATTEMPTS
+        ))
+    }
+
+    // the edited text and its label, None when tree has no place for the edit
+    pub fn apply<R: Rng + ?Sized>(
+        &self,
+        kind: ErrorKind,
+        tree: &Derivation,
+        rng: &mut R,
+    ) -> Option<(String, String)> {
+        let text = tree.text();
+        let (mut leaves, mut groups) = (vec![], vec![]);
+        spans(tree, 0, &mut leaves, &mut groups);
+        let leaf = leaves.choose(rng).copied();
+        let edit = |(s, e): Span, with: &str| format!("{}{}{}", &text[..s], with, &text[e..]);
+        match kind {
+            ErrorKind::Drop => {
+                let (s, e) = leaf?;
+                Some((edit((s, e), ""), format!("drop {:?} at {}", &text[s..e], s)))
+            }
+            ErrorKind::Duplicate => {
+                let (s, e) = leaf?;
+                let t = &text[s..e];
+                Some((
+                    edit((s, e), &t.repeat(2)),
+                    format!("duplicate {:?} at {}", t, s),
+                ))
+            }
+            ErrorKind::Swap => {
+                let group = groups.choose(rng)?;
+                let mut two: Vec<Span> = group.choose_multiple(rng, 2).copied().collect();
+                two.sort();
+                let ((s1, e1), (s2, e2)) = (two[0], two[1]);
+                let swapped = format!(
+                    "{}{}{}{}{}",
+                    &text[..s1],
+                    &text[s2..e2],
+                    &text[e1..s2],
+                    &text[s1..e1],
+                    &text[e2..]
+                );
+                let label = format!(
+                    "swap {:?} at {} with {:?} at {}",
+                    &text[s1..e1],
+                    s1,
+                    &text[s2..e2],
+                    s2
+                );
+                Some((swapped, label))
+            }
+            ErrorKind::Substitute => {
+                let (s, e) = leaf?;
+                let t = &text[s..e];
+                let other = self
+                    .terminals
+                    .iter()
+                    .filter(|o| *o != t)
+                    .collect::<Vec<_>>();
+                let with = other.choose(rng)?;
+                Some((
+                    edit((s, e), with),
+                    format!("substitute {:?} at {} by {:?}", t, s, with),
+                ))
+            }
+            ErrorKind::Truncate => {
+                // keep a non-empty prefix when there is one
+                let cuts: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+                let at = *cuts[cuts.len().clamp(1, 2) - 1..].choose(rng)?;
+                Some((text[..at].to_string(), format!("truncate at {}", at)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn confirmed_invalid() {
+        let mut c = Collection::new();
+        c.add(r#"<e>::=<e>"+"<t>|<t>"#).unwrap();
+        c.add(r#"<t>::="/"<e>"/"|"1"|"2""#).unwrap();
+        let n = c.negative_sampler("<e>").unwrap();
+        let mut rng = StdRng::seed_from_u64(3);
+        for kind in ALL_ERRORS {
+            for _ in 0..20 {
+                let s = n.sample(&[kind], &mut rng).unwrap();
+                assert_eq!(s.kind, kind);
+                assert!(s.label.starts_with(&kind.to_string()));
+                assert!(!c.accepts("<e>", &s.text).unwrap(), "{:?}", s);
+                assert!(c.accepts("<e>", &s.valid).unwrap());
+            }
+        }
+        assert_eq!("swap".parse::<ErrorKind>(), Ok(ErrorKind::Swap));
+        // the valid sample is generated like gen, built-ins included
+        c.add(r#"<r>::=<@int(10,20)>"x""#).unwrap();
+        let n = c.negative_sampler("<r>").unwrap();
+        for _ in 0..20 {
+            let s = n.sample(&ALL_ERRORS, &mut rng).unwrap();
+            let v: u32 = s.valid.trim_end_matches('x').parse().unwrap();
+            assert!((10..=20).contains(&v), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn spans_of_deep_tree() {
+        let node = |children| Derivation::Node {
+            rule: "<l>".to_string(),
+            alt: 0,
+            children,
+        };
+        let leaf = |s: &str| Derivation::Leaf(s.to_string());
+        let mut tree = node(vec![leaf("ab"), leaf("")]);
+        let (mut leaves, mut groups) = (vec![], vec![]);
+        assert_eq!(spans(&tree, 0, &mut leaves, &mut groups), 2);
+        assert_eq!((leaves, groups), (vec![(0, 2)], vec![]));
+        let n = 5000;
+        for _ in 0..n {
+            tree = node(vec![leaf("x"), tree]);
+        }
+        // on a stack far too small to recurse that deep
+        std::thread::Builder::new()
+            .stack_size(64 * 1024)
+            .spawn(move || {
+                let (mut leaves, mut groups) = (vec![], vec![]);
+                assert_eq!(spans(&tree, 0, &mut leaves, &mut groups), n + 2);
+                assert_eq!(leaves.len(), n + 1);
+                assert_eq!(groups.len(), n);
+                assert_eq!(groups[0], vec![(n - 1, n), (n, n + 2)]);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+}
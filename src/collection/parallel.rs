@@ -0,0 +1,120 @@
+// batch generation on std threads
+//
+// sample i is always generated from its own rng seeded by sample_seed(master, i),
+// so the output does not depend on the number of threads, and any single sample
+// can be regenerated with gen_seeded(bnf, sample_seed(master, i))
+use super::{Collection, Derivation};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::thread;
+
+// splitmix64 finalizer over (master, index), neighbouring indices get unrelated seeds
+pub fn sample_seed(master: u64, index: u64) -> u64 {
+    let mut z = master
+        .wrapping_add(index.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+        .wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+impl Collection {
+    pub fn gen_seeded(&self, bnf: &str, seed: u64) -> Result<String, String> {
+        self.gen_with_rng(bnf, &mut StdRng::seed_from_u64(seed))
+    }
+
+    pub fn gen_tree_seeded(&self, bnf: &str, seed: u64) -> Result<Derivation, String> {
+        let weights = &self.boltzmann(bnf)?.weights;
+        self.gen_tree(bnf, weights, &mut StdRng::seed_from_u64(seed))
+    }
+
+    // generate samples [0, count) of bnf on `threads` threads, results are in index order
+    pub fn gen_batch(
+        &self,
+        bnf: &str,
+        master_seed: u64,
+        count: usize,
+        threads: usize,
+    ) -> Vec<Result<String, String>> {
+        batch(count, threads, |i| {
+            self.gen_seeded(bnf, sample_seed(master_seed, i))
+        })
+    }
+
+    // like gen_batch, keeping the derivation trees
+    pub fn gen_tree_batch(
+        &self,
+        bnf: &str,
+        master_seed: u64,
+        count: usize,
+        threads: usize,
+    ) -> Vec<Result<Derivation, String>> {
+        batch(count, threads, |i| {
+            self.gen_tree_seeded(bnf, sample_seed(master_seed, i))
+        })
+    }
+}
+
+// results of f for the indexes [0, count) on `threads` threads, in index order
+fn batch<T, F>(count: usize, threads: usize, f: F) -> Vec<Result<T, String>>
+where
+    T: Send,
+    F: Fn(u64) -> Result<T, String> + Sync,
+{
+    let mut out: Vec<Result<T, String>> = (0..count).map(|_| Err(String::new())).collect();
+    if count == 0 {
+        return out;
+    }
+    let chunk = count.div_ceil(threads.clamp(1, count));
+    let f = &f;
+    thread::scope(|s| {
+        for (n, part) in out.chunks_mut(chunk).enumerate() {
+            s.spawn(move || {
+                for (i, slot) in part.iter_mut().enumerate() {
+                    *slot = f((n * chunk + i) as u64);
+                }
+            });
+        }
+    });
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collection() -> Collection {
+        let mut c = Collection::new();
+        c.add(r#"<output>::=<digit><output>|<digit>"#).unwrap();
+        c.add(r#"<digit>::="0"|"1"|"2"|"3"|"4"|"5"|"6"|"7"|"8"|"9""#)
+            .unwrap();
+        c
+    }
+
+    #[test]
+    fn collection_is_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Collection>();
+    }
+
+    #[test]
+    fn batch_independent_of_threads() {
+        let c = collection();
+        let one = c.gen_batch("<output>", 42, 50, 1);
+        for threads in [2, 3, 8, 64] {
+            assert_eq!(one, c.gen_batch("<output>", 42, 50, threads));
+        }
+    }
+
+    #[test]
+    fn sample_reproducible_from_index() {
+        let c = collection();
+        let batch = c.gen_batch("<output>", 7, 20, 4);
+        assert_eq!(batch[13], c.gen_seeded("<output>", sample_seed(7, 13)));
+        // the same samples with their trees
+        let trees = c.gen_tree_batch("<output>", 7, 20, 3);
+        for (t, s) in trees.iter().zip(&batch) {
+            assert_eq!(&t.as_ref().unwrap().text(), s.as_ref().unwrap());
+        }
+    }
+}
@@ -0,0 +1,302 @@
+// ranking and unranking of derivations
+//
+// Derivations of bnf are numbered by size first, then within one size by alternative
+// and by how the budget is split among the nonterminals of the alternative, the
+// first one varying slowest. The numbering does not depend on the size bound, so
+// workers can take disjoint ranges of [0, count(bnf, n)) and any sample is
+// regenerated from its index alone.
+use super::uniform::Alt;
+use super::{Collection, Counts, Derivation, SizeMetric};
+use crate::bigint::BigUint;
+use crate::parser::Symbol;
+
+// a node being unranked: its alternative, sizes and ranks of the subtrees still to
+// build, and the children built so far
+struct Frame<'a> {
+    rule: &'a str,
+    a: usize,
+    alt: &'a Alt,
+    parts: std::vec::IntoIter<(usize, BigUint)>,
+    children: Vec<Derivation>,
+}
+
+impl Counts {
+    // number of derivations of bnf with size at most n
+    pub fn count_upto(&self, bnf: &str, n: usize) -> BigUint {
+        (0..=n).fold(BigUint::zero(), |acc, i| &acc + &self.count(bnf, i))
+    }
+
+    // the k-th derivation of bnf among those with size at most n
+    pub fn unrank(&self, bnf: &str, n: usize, k: &BigUint) -> Result<Derivation, String> {
+        self.check(bnf, n)?;
+        let mut k = k.clone();
+        for size in 0..=n {
+            let c = &self.total[bnf][size];
+            if k < *c {
+                return self.unrank_exact(bnf, size, &k);
+            }
+            k = &k - c;
+        }
+        Err(format!(
+            "{} has only {} derivations of size at most {}",
+            bnf,
+            self.count_upto(bnf, n),
+            n
+        ))
+    }
+
+    // the k-th derivation of bnf among those with size exactly n
+    pub fn unrank_exact(&self, bnf: &str, n: usize, k: &BigUint) -> Result<Derivation, String> {
+        self.check(bnf, n)?;
+        if *k >= self.total[bnf][n] {
+            return Err(format!(
+                "{} has only {} derivations of size {}",
+                bnf, self.total[bnf][n], n
+            ));
+        }
+        Ok(self.unrank_node(bnf, n, k.clone()))
+    }
+
+    fn check(&self, bnf: &str, n: usize) -> Result<(), String> {
+        if !self.rules.contains_key(bnf) {
+            return Err(format!("No production rule for {}", bnf));
+        }
+        if n > self.max {
+            return Err(format!("size {} is beyond the counted {}", n, self.max));
+        }
+        Ok(())
+    }
+
+    // the node of size n and rank k among those of bnf
+    fn frame<'a>(&'a self, bnf: &'a str, n: usize, mut k: BigUint) -> Frame<'a> {
+        let (a, alt) = self.rules[bnf]
+            .iter()
+            .enumerate()
+            .filter(|(_, alt)| alt.base <= n)
+            .find(|(_, alt)| {
+                let w = &alt.suffix[0][n - alt.base];
+                if k < *w {
+                    true
+                } else {
+                    k = &k - w;
+                    false
+                }
+            })
+            .expect("rank is below the count");
+        let mut budget = n - alt.base;
+        let mut parts = vec![];
+        for (i, c) in alt.children.iter().enumerate() {
+            let j = (0..=budget)
+                .find(|&j| {
+                    let w = &self.total[c][j] * &alt.suffix[i + 1][budget - j];
+                    if k < w {
+                        true
+                    } else {
+                        k = &k - &w;
+                        false
+                    }
+                })
+                .expect("rank is below the count");
+            let (child, rest) = k.div_rem(&alt.suffix[i + 1][budget - j]);
+            parts.push((j, child));
+            k = rest;
+            budget -= j;
+        }
+        Frame {
+            rule: bnf,
+            a,
+            alt,
+            parts: parts.into_iter(),
+            children: vec![],
+        }
+    }
+
+    // top down with an explicit stack, trees can be as deep as n
+    fn unrank_node(&self, bnf: &str, n: usize, k: BigUint) -> Derivation {
+        let mut frames = vec![self.frame(bnf, n, k)];
+        loop {
+            let f = frames.last_mut().unwrap();
+            let alt = f.alt;
+            match alt.syms.get(f.children.len()) {
+                Some(Symbol::Terminal(t)) => f.children.push(Derivation::Leaf(t.clone())),
+                Some(Symbol::NonTerminal(t)) => {
+                    let (j, child) = f.parts.next().unwrap();
+                    frames.push(self.frame(t, j, child));
+                }
+                None => {
+                    let f = frames.pop().unwrap();
+                    let node = Derivation::Node {
+                        rule: f.rule.to_string(),
+                        alt: f.a,
+                        children: f.children,
+                    };
+                    match frames.last_mut() {
+                        Some(parent) => parent.children.push(node),
+                        None => return node,
+                    }
+                }
+            }
+        }
+    }
+
+    // inverse of unrank, for any bound at least the size of tree
+    pub fn rank(&self, tree: &Derivation) -> Result<BigUint, String> {
+        let (bnf, size) = match tree {
+            Derivation::Node { rule, .. } => (rule, tree.size(self.metric)),
+            Derivation::Leaf(s) => return Err(format!("\"{}\" is not a derivation", s)),
+        };
+        self.check(bnf, size)?;
+        let below = if size == 0 {
+            BigUint::zero()
+        } else {
+            self.count_upto(bnf, size - 1)
+        };
+        Ok(&below + &self.rank_exact(tree)?)
+    }
+
+    // position of tree among the derivations of its rule and size, bottom up with an
+    // explicit stack
+    pub fn rank_exact(&self, tree: &Derivation) -> Result<BigUint, String> {
+        let top = self.node(tree)?;
+        self.check(top.0, tree.size(self.metric))?;
+        // nodes being ranked as (rule, alternative, children, next child, sizes and
+        // ranks of the subtrees done so far)
+        let mut frames = vec![(top, 0, vec![])];
+        loop {
+            let ((_, _, children), next, _) = frames.last_mut().unwrap();
+            match children.get(*next) {
+                Some(Derivation::Leaf(_)) => *next += 1,
+                Some(sub) => {
+                    *next += 1;
+                    frames.push((self.node(sub)?, 0, vec![]));
+                }
+                None => {
+                    let ((rule, a, _), _, done) = frames.pop().unwrap();
+                    let ranked = self.rank_node(rule, a, &done);
+                    match frames.last_mut() {
+                        Some((_, _, siblings)) => siblings.push(ranked),
+                        None => return Ok(ranked.1),
+                    }
+                }
+            }
+        }
+    }
+
+    // rule, alternative and children of a node matching its alternative
+    fn node<'t>(&self, tree: &'t Derivation) -> Result<(&'t str, usize, &'t [Derivation]), String> {
+        let (rule, a, children) = match tree {
+            Derivation::Node {
+                rule,
+                alt,
+                children,
+            } => (rule, *alt, children),
+            Derivation::Leaf(s) => return Err(format!("\"{}\" is not a derivation", s)),
+        };
+        let alt = self
+            .rules
+            .get(rule)
+            .ok_or_else(|| format!("No production rule for {}", rule))?
+            .get(a)
+            .ok_or_else(|| format!("{} has no alternative {}", rule, a))?;
+        let matches = alt.syms.len() == children.len()
+            && alt.syms.iter().zip(children).all(|(s, c)| match (s, c) {
+                (Symbol::Terminal(t), Derivation::Leaf(l)) => t == l,
+                (Symbol::NonTerminal(t), Derivation::Node { rule, .. }) => t == rule,
+                _ => false,
+            });
+        if !matches {
+            return Err(format!(
+                "children of {} do not match its alternative {}",
+                rule, a
+            ));
+        }
+        Ok((rule, a, children))
+    }
+
+    // size and rank of a node given those of its subtrees
+    fn rank_node(&self, rule: &str, a: usize, subs: &[(usize, BigUint)]) -> (usize, BigUint) {
+        let alts = &self.rules[rule];
+        let alt = &alts[a];
+        let n = alt.base + subs.iter().map(|(j, _)| j).sum::<usize>();
+        let mut k = BigUint::zero();
+        for before in alts[..a].iter().filter(|alt| alt.base <= n) {
+            k += &before.suffix[0][n - before.base];
+        }
+        let mut budget = n - alt.base;
+        for (i, (c, (j, sub))) in alt.children.iter().zip(subs).enumerate() {
+            for smaller in 0..*j {
+                k += &(&self.total[c][smaller] * &alt.suffix[i + 1][budget - smaller]);
+            }
+            k += &(sub * &alt.suffix[i + 1][budget - j]);
+            budget -= j;
+        }
+        (n, k)
+    }
+}
+
+// each of these counts the grammar anew, callers ranking or unranking many
+// derivations should hold the Counts of counts() instead
+impl Collection {
+    // number of derivations of bnf with text length at most n
+    pub fn count(&self, bnf: &str, n: usize) -> Result<BigUint, String> {
+        Ok(self.counts(bnf, SizeMetric::Text, n)?.count_upto(bnf, n))
+    }
+
+    pub fn unrank(&self, bnf: &str, n: usize, k: &BigUint) -> Result<Derivation, String> {
+        self.counts(bnf, SizeMetric::Text, n)?.unrank(bnf, n, k)
+    }
+
+    pub fn rank(&self, tree: &Derivation) -> Result<BigUint, String> {
+        let bnf = match tree {
+            Derivation::Node { rule, .. } => rule,
+            Derivation::Leaf(s) => return Err(format!("\"{}\" is not a derivation", s)),
+        };
+        self.counts(bnf, SizeMetric::Text, tree.size(SizeMetric::Text))?
+            .rank(tree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bijection() {
+        let mut c = Collection::new();
+        c.add(r#"<e>::=<e>"+"<e>|"-"<e>|<d>"#).unwrap();
+        c.add(r#"<d>::="1"|"2"|E"#).unwrap();
+        let n = 5;
+        let total = c.count("<e>", n).unwrap();
+        let counts = c.counts("<e>", SizeMetric::Text, n).unwrap();
+        let mut seen = std::collections::HashSet::new();
+        let mut k = BigUint::zero();
+        while k < total {
+            let d = counts.unrank("<e>", n, &k).unwrap();
+            assert!(d.size(SizeMetric::Text) <= n);
+            assert_eq!(c.rank(&d).unwrap(), k);
+            assert!(seen.insert(d));
+            k += &BigUint::one();
+        }
+        assert!(counts.unrank("<e>", n, &total).is_err());
+    }
+
+    #[test]
+    fn deep() {
+        let mut c = Collection::new();
+        c.add(r#"<b>::=<b>"0"|E"#).unwrap();
+        let n = 1000;
+        let counts = c.counts("<b>", SizeMetric::Text, n).unwrap();
+        let k = &counts.count_upto("<b>", n) - &BigUint::one();
+        // a tree n deep on a stack far too small to recurse that deep
+        std::thread::Builder::new()
+            .stack_size(64 * 1024)
+            .spawn(move || {
+                let d = counts.unrank("<b>", n, &k).unwrap();
+                assert_eq!(d.text(), "0".repeat(n));
+                assert_eq!(counts.rank(&d).unwrap(), k);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+}
@@ -0,0 +1,343 @@
+// recording and replaying the decisions of a generation
+//
+// A sample is fully determined by the sequence of choices gen_from_ast made, so a
+// recorded sequence reproduces it exactly whatever rng produced it. Sequences are
+// written as a JSON array, alternatives as numbers and the other kinds as objects
+// like {"repeat":3}, or compactly as base64 of varints.
+use super::{Chooser, Collection, Sampler};
+use crate::codec::{base64_decode, base64_encode, json_escape};
+use rand::{Rng, RngCore};
+use std::iter::Peekable;
+use std::str::Chars;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Choice {
+    Alternative(usize),
+    Repeat(usize), // count of a repetition in a regex
+    Pick(usize),   // character or branch in a regex
+    Value(String), // output of a built-in
+}
+
+impl Choice {
+    // varint kind and value, a Value is followed by its bytes
+    fn kind(&self) -> (u64, usize) {
+        match self {
+            Choice::Alternative(a) => (0, *a),
+            Choice::Repeat(r) => (1, *r),
+            Choice::Pick(p) => (2, *p),
+            Choice::Value(v) => (3, v.len()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Choices(pub Vec<Choice>);
+
+// passes the choices of inner through, keeping a copy
+pub struct Recorder<'c, C: Chooser + ?Sized> {
+    pub inner: &'c mut C,
+    pub choices: Choices,
+}
+
+impl<C: Chooser + ?Sized> Chooser for Recorder<'_, C> {
+    fn alternative(&mut self, rule: &str, n: usize) -> Result<usize, String> {
+        let a = self.inner.alternative(rule, n)?;
+        self.choices.0.push(Choice::Alternative(a));
+        Ok(a)
+    }
+
+    fn repeat(&mut self, min: usize, max: usize) -> Result<usize, String> {
+        let r = self.inner.repeat(min, max)?;
+        self.choices.0.push(Choice::Repeat(r));
+        Ok(r)
+    }
+
+    fn pick(&mut self, n: usize) -> Result<usize, String> {
+        let p = self.inner.pick(n)?;
+        self.choices.0.push(Choice::Pick(p));
+        Ok(p)
+    }
+
+    fn value(&mut self, gen: &mut dyn FnMut(&mut dyn RngCore) -> String) -> Result<String, String> {
+        let v = self.inner.value(gen)?;
+        self.choices.0.push(Choice::Value(v.clone()));
+        Ok(v)
+    }
+}
+
+// makes the recorded choices again, in order
+pub struct Replayer<'c> {
+    choices: &'c [Choice],
+}
+
+impl Replayer<'_> {
+    fn next(&mut self) -> Result<&Choice, String> {
+        let (first, rest) = self
+            .choices
+            .split_first()
+            .ok_or_else(|| "choice sequence ends early".to_string())?;
+        self.choices = rest;
+        Ok(first)
+    }
+}
+
+impl Chooser for Replayer<'_> {
+    fn alternative(&mut self, rule: &str, n: usize) -> Result<usize, String> {
+        match self.next()? {
+            Choice::Alternative(a) if *a < n => Ok(*a),
+            c => Err(format!("recorded {:?} does not fit {}", c, rule)),
+        }
+    }
+
+    fn repeat(&mut self, min: usize, max: usize) -> Result<usize, String> {
+        match self.next()? {
+            Choice::Repeat(r) if (min..=max).contains(r) => Ok(*r),
+            c => Err(format!("recorded {:?} does not fit {{{},{}}}", c, min, max)),
+        }
+    }
+
+    fn pick(&mut self, n: usize) -> Result<usize, String> {
+        match self.next()? {
+            Choice::Pick(p) if *p < n => Ok(*p),
+            c => Err(format!("recorded {:?} does not fit a pick of {}", c, n)),
+        }
+    }
+
+    fn value(&mut self, _: &mut dyn FnMut(&mut dyn RngCore) -> String) -> Result<String, String> {
+        match self.next()? {
+            Choice::Value(v) => Ok(v.clone()),
+            c => Err(format!("recorded {:?} does not fit a value", c)),
+        }
+    }
+}
+
+// the JSON subset to_json writes: numbers, strings and one-key objects
+fn skip_space(it: &mut Peekable<Chars>) {
+    while it.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+fn json_number(it: &mut Peekable<Chars>) -> Result<usize, String> {
+    skip_space(it);
+    let mut digits = String::new();
+    while let Some(c) = it.next_if(char::is_ascii_digit) {
+        digits.push(c);
+    }
+    digits
+        .parse()
+        .map_err(|_| format!("expect a number before {:?}", it.peek()))
+}
+
+fn json_string(it: &mut Peekable<Chars>) -> Result<String, String> {
+    skip_space(it);
+    if it.next() != Some('"') {
+        return Err("expect a string".to_string());
+    }
+    let mut out = String::new();
+    loop {
+        match it.next().ok_or("unclosed string")? {
+            '"' => return Ok(out),
+            '\\' => out.push(match it.next().ok_or("unclosed string")? {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                'b' => '\u{8}',
+                'f' => '\u{c}',
+                'u' => {
+                    let hex: String = it.by_ref().take(4).collect();
+                    u32::from_str_radix(&hex, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| format!("bad escape \\u{}", hex))?
+                }
+                c => c,
+            }),
+            c => out.push(c),
+        }
+    }
+}
+
+fn json_choice(it: &mut Peekable<Chars>) -> Result<Choice, String> {
+    skip_space(it);
+    if it.next_if_eq(&'{').is_none() {
+        return json_number(it).map(Choice::Alternative);
+    }
+    let key = json_string(it)?;
+    skip_space(it);
+    if it.next() != Some(':') {
+        return Err(format!("expect : after {:?}", key));
+    }
+    let choice = match key.as_str() {
+        "repeat" => Choice::Repeat(json_number(it)?),
+        "pick" => Choice::Pick(json_number(it)?),
+        "value" => Choice::Value(json_string(it)?),
+        _ => return Err(format!("{} is not a choice", key)),
+    };
+    skip_space(it);
+    match it.next() {
+        Some('}') => Ok(choice),
+        _ => Err(format!("expect }} after {:?}", choice)),
+    }
+}
+
+impl Choices {
+    pub fn to_json(&self) -> String {
+        let items: Vec<String> = self
+            .0
+            .iter()
+            .map(|c| match c {
+                Choice::Alternative(a) => a.to_string(),
+                Choice::Repeat(r) => format!("{{\"repeat\":{}}}", r),
+                Choice::Pick(p) => format!("{{\"pick\":{}}}", p),
+                Choice::Value(v) => format!("{{\"value\":\"{}\"}}", json_escape(v)),
+            })
+            .collect();
+        format!("[{}]", items.join(","))
+    }
+
+    pub fn from_json(s: &str) -> Result<Self, String> {
+        let mut it = s.chars().peekable();
+        skip_space(&mut it);
+        if it.next() != Some('[') {
+            return Err(format!("{} is not a JSON array", s));
+        }
+        let mut choices = vec![];
+        skip_space(&mut it);
+        if it.next_if_eq(&']').is_none() {
+            loop {
+                choices.push(json_choice(&mut it)?);
+                skip_space(&mut it);
+                match it.next() {
+                    Some(',') => (),
+                    Some(']') => break,
+                    _ => return Err(format!("{} is not a JSON array", s)),
+                }
+            }
+        }
+        skip_space(&mut it);
+        match it.next() {
+            None => Ok(Choices(choices)),
+            Some(_) => Err(format!("{} is not a JSON array", s)),
+        }
+    }
+
+    // each choice is a LEB128 varint of (value << 2 | kind), kind 0 for alternatives,
+    // 1 for repeats, 2 for picks and 3 for values, whose utf-8 bytes follow with the
+    // length as value
+    pub fn to_base64(&self) -> String {
+        let mut bytes = vec![];
+        for c in &self.0 {
+            let (kind, value) = c.kind();
+            let mut v = (value as u64) << 2 | kind;
+            while v >= 0x80 {
+                bytes.push(v as u8 | 0x80);
+                v >>= 7;
+            }
+            bytes.push(v as u8);
+            if let Choice::Value(s) = c {
+                bytes.extend(s.as_bytes());
+            }
+        }
+        base64_encode(&bytes)
+    }
+
+    pub fn from_base64(s: &str) -> Result<Self, String> {
+        let bytes = base64_decode(s.trim())?;
+        let mut choices = vec![];
+        let mut iter = bytes.into_iter();
+        while let Some(mut b) = iter.next() {
+            let mut v = 0u64;
+            let mut shift = 0;
+            while b & 0x80 != 0 {
+                v |= ((b & 0x7f) as u64) << shift;
+                shift += 7;
+                b = iter.next().ok_or("choice sequence is cut off")?;
+                if shift > 63 {
+                    return Err("choice out of range".to_string());
+                }
+            }
+            v |= (b as u64) << shift;
+            match v & 3 {
+                0 => choices.push(Choice::Alternative((v >> 2) as usize)),
+                1 => choices.push(Choice::Repeat((v >> 2) as usize)),
+                2 => choices.push(Choice::Pick((v >> 2) as usize)),
+                _ => {
+                    let bytes: Vec<u8> = iter.by_ref().take((v >> 2) as usize).collect();
+                    if bytes.len() < (v >> 2) as usize {
+                        return Err("choice sequence is cut off".to_string());
+                    }
+                    let s = String::from_utf8(bytes).map_err(|_| "value is not utf-8")?;
+                    choices.push(Choice::Value(s));
+                }
+            }
+        }
+        Ok(Choices(choices))
+    }
+}
+
+impl Collection {
+    // gen_with_rng, also returning the choices made
+    pub fn gen_recorded<R: Rng + ?Sized>(
+        &self,
+        bnf: &str,
+        rng: &mut R,
+    ) -> Result<(String, Choices), String> {
+        let weights = &self.boltzmann(bnf)?.weights;
+        let mut recorder = Recorder {
+            inner: &mut Sampler { weights, rng },
+            choices: Choices::default(),
+        };
+        let text = self.gen_with(bnf, &mut recorder)?;
+        Ok((text, recorder.choices))
+    }
+
+    pub fn replay(&self, bnf: &str, choices: &Choices) -> Result<String, String> {
+        let mut replayer = Replayer {
+            choices: &choices.0,
+        };
+        let text = self.gen_with(bnf, &mut replayer)?;
+        match replayer.choices.len() {
+            0 => Ok(text),
+            n => Err(format!("{} recorded choices left over", n)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn replay_reproduces() {
+        let mut c = Collection::new();
+        c.add(r#"<e>::=<e>"+"<e>|<e>"*"<e>|<d>"#).unwrap();
+        c.add(r#"<d>::="0"|"1"|"2"|"3"|"4"|"5"|"6"|"7"|"8"|"9"|/x[a-f]{1,3}/"#)
+            .unwrap();
+        let mut rng = StdRng::seed_from_u64(11);
+        for _ in 0..20 {
+            let (text, choices) = c.gen_recorded("<e>", &mut rng).unwrap();
+            let json = Choices::from_json(&choices.to_json()).unwrap();
+            let b64 = Choices::from_base64(&choices.to_base64()).unwrap();
+            assert_eq!((&json, &b64), (&choices, &choices));
+            assert_eq!(c.replay("<e>", &choices).unwrap(), text);
+        }
+        assert_eq!(Choices::from_json("[2, 7]").unwrap().to_base64(), "CBw=");
+        let mixed = Choices::from_json(r#"[10, {"repeat":2}, {"pick": 0}, {"pick":5}]"#).unwrap();
+        assert_eq!(c.replay("<d>", &mixed).unwrap(), "xaf");
+        c.add(r#"<n>::=<@int(1,1000)>"+"<d>"#).unwrap();
+        let (text, choices) = c.gen_recorded("<n>", &mut rng).unwrap();
+        assert!(matches!(choices.0[1], Choice::Value(_)));
+        let b64 = Choices::from_base64(&choices.to_base64()).unwrap();
+        assert_eq!(c.replay("<n>", &b64).unwrap(), text);
+        let quoted = Choices::from_json(r#"[{"value":"a,\"b\"\u00e9"}]"#).unwrap();
+        assert_eq!(quoted.0, [Choice::Value("a,\"b\"\u{e9}".to_string())]);
+        assert_eq!(Choices::from_json(&quoted.to_json()).unwrap(), quoted);
+        assert!(c
+            .replay("<e>", &Choices::from_json("[0]").unwrap())
+            .is_err());
+        assert!(c
+            .replay("<e>", &Choices::from_json("[5]").unwrap())
+            .is_err());
+    }
+}
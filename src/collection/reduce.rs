@@ -0,0 +1,106 @@
+// grammar aware test case reduction, in the style of Perses and HDD
+//
+// Going through the tree top down, a subtree is replaced by the smallest
+// derivation of its nonterminal or by one of its own smaller subtrees of the same
+// nonterminal, whenever the result is still interesting. Each replacement keeps
+// the tree a derivation of the start symbol, so the input stays valid. Passes are
+// repeated until none of them makes progress.
+use super::analysis::min_alternatives;
+use super::mutate::{nodes, path, replaced};
+use super::{Collection, Derivation, Rules, SizeMetric};
+use crate::parser::Symbol;
+use std::collections::{HashMap, HashSet};
+
+impl Collection {
+    // the shortest text derivation of every productive nonterminal
+    pub fn min_derivations(&self) -> Result<HashMap<String, Derivation>, String> {
+        Ok(min_derivations(&self.rules()?))
+    }
+
+    // shrink tree while interesting holds for its text, tree must satisfy it to begin with
+    pub fn reduce<F>(&self, tree: &Derivation, mut interesting: F) -> Result<Derivation, String>
+    where
+        F: FnMut(&str) -> Result<bool, String>,
+    {
+        let min = self.min_derivations()?;
+        let mut tree = tree.clone();
+        let mut tested: HashSet<String> = HashSet::new();
+        tested.insert(tree.text());
+        'pass: loop {
+            let size = tree.size(SizeMetric::Text);
+            let all = nodes(&tree);
+            for (i, (_, node)) in all.iter().enumerate() {
+                let rule = match node {
+                    Derivation::Node { rule, .. } => rule,
+                    Derivation::Leaf(_) => continue,
+                };
+                let mut candidates: Vec<&Derivation> = nodes(node)
+                    .into_iter()
+                    .skip(1)
+                    .map(|(_, d)| d)
+                    .filter(|d| matches!(d, Derivation::Node { rule: r, .. } if r == rule))
+                    .chain(min.get(rule))
+                    .collect();
+                candidates.sort_by_key(|d| d.size(SizeMetric::Text));
+                for c in candidates {
+                    let new = replaced(self, &tree, &path(&all, i), c.clone())?;
+                    let text = new.text();
+                    if text.len() >= size || !tested.insert(text.clone()) {
+                        continue;
+                    }
+                    if interesting(&text)? {
+                        tree = new;
+                        continue 'pass;
+                    }
+                }
+            }
+            return Ok(tree);
+        }
+    }
+}
+
+pub(crate) fn min_derivations(rules: &Rules) -> HashMap<String, Derivation> {
+    fn build(rules: &Rules, best: &HashMap<String, (usize, usize)>, k: &String) -> Derivation {
+        let alt = best[k].1;
+        Derivation::Node {
+            rule: k.clone(),
+            alt,
+            children: rules[k][alt]
+                .iter()
+                .map(|s| match s {
+                    Symbol::Terminal(t) => Derivation::Leaf(t.clone()),
+                    Symbol::NonTerminal(t) => build(rules, best, t),
+                })
+                .collect(),
+        }
+    }
+    let best = min_alternatives(rules);
+    best.keys()
+        .map(|k| (k.clone(), build(rules, &best, k)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reduces_to_fixpoint() {
+        let mut c = Collection::new();
+        c.add(r#"<e>::=<e>"+"<t>|<t>"#).unwrap();
+        c.add(r#"<t>::="/"<e>"/"|"11"|"2""#).unwrap();
+        let min = c.min_derivations().unwrap();
+        assert_eq!(min["<e>"].text(), "2");
+        let tree = c.parse("<e>", "11+/11+/2+11//+11").unwrap().tree();
+        let mut runs = 0;
+        let small = c
+            .reduce(&tree, |s| {
+                runs += 1;
+                Ok(s.contains("+/"))
+            })
+            .unwrap();
+        assert_eq!(small.text(), "2+/2/");
+        assert!(c.accepts("<e>", &small.text()).unwrap());
+        assert!(runs < 40, "{}", runs);
+    }
+}
@@ -0,0 +1,286 @@
+// uniform sampling of derivations of an exact size
+//
+// count[A][n] is the number of derivations of <A> with size n, computed level by
+// level for n = 0..=max. Every alternative keeps suffix[i][b], the number of ways
+// its nonterminals i.. can share a budget b, so a sample is drawn top down by
+// picking alternatives and budget splits proportional to these counts.
+// For an unambiguous grammar, uniform derivations are uniform strings.
+//
+// Only the rules reachable from the start are counted. Values made otherwise, like
+// those of built-ins, are refused: counting their shapes would give texts gen never
+// produces.
+use super::{reached, Collection, Derivation, Rules};
+use crate::bigint::BigUint;
+use crate::parser::{Expr0, Symbol};
+use rand::Rng;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SizeMetric {
+    Text,  // length of the generated text
+    Nodes, // number of expanded nonterminals
+}
+
+impl SizeMetric {
+    // size an alternative adds on top of the sizes of its nonterminals
+    pub fn base(self, syms: &[Symbol]) -> usize {
+        match self {
+            SizeMetric::Text => syms
+                .iter()
+                .map(|s| match s {
+                    Symbol::Terminal(t) => t.len(),
+                    _ => 0,
+                })
+                .sum(),
+            SizeMetric::Nodes => 1,
+        }
+    }
+}
+
+pub(super) struct Alt {
+    pub(super) syms: Vec<Symbol>,
+    pub(super) base: usize, // size contributed by the alternative itself
+    pub(super) children: Vec<String>, // nonterminals of syms, in order
+    pub(super) suffix: Vec<Vec<BigUint>>,
+}
+
+pub struct Counts {
+    pub(super) metric: SizeMetric,
+    pub(super) max: usize,
+    pub(super) rules: HashMap<String, Vec<Alt>>,
+    pub(super) total: HashMap<String, Vec<BigUint>>,
+}
+
+impl Collection {
+    // derivations of bnf and the rules it reaches
+    pub fn counts(&self, bnf: &str, metric: SizeMetric, max: usize) -> Result<Counts, String> {
+        let rules = reached(self.all_rules()?, bnf)?;
+        self.countable(&rules)?;
+        Counts::new(rules, metric, max)
+    }
+
+    // the first value among the rules that is not derived by them
+    fn countable(&self, rules: &Rules) -> Result<(), String> {
+        let mut names: Vec<&String> = self.h.keys().filter(|k| rules.contains_key(*k)).collect();
+        names.sort();
+        for name in names {
+            for e0 in self.h[name].expr0s() {
+                let what = match e0 {
+                    Expr0::Builtin { builtin } => format!("built-in {}", builtin.key()),
+                    Expr0::Computed { field } => format!("computed field {}", field.key()),
+                    Expr0::Bind { var, .. } => format!("binding of {}", var),
+                    Expr0::Transform { transform, .. } => {
+                        format!("transformer @{}", transform.name)
+                    }
+                    _ => continue,
+                };
+                return Err(format!(
+                    "{} in {} can not be counted, sizes, enumeration and ranking need plain rules",
+                    what, name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // build Counts once and call Counts::sample when drawing many samples
+    pub fn gen_uniform<R: Rng + ?Sized>(
+        &self,
+        bnf: &str,
+        n: usize,
+        metric: SizeMetric,
+        rng: &mut R,
+    ) -> Result<String, String> {
+        Ok(self.counts(bnf, metric, n)?.sample(bnf, n, rng)?.text())
+    }
+}
+
+impl Counts {
+    // the rules as given, values made otherwise by their shape
+    pub(super) fn new(
+        rules: HashMap<String, Vec<Vec<Symbol>>>,
+        metric: SizeMetric,
+        max: usize,
+    ) -> Result<Self, String> {
+        let rules: HashMap<String, Vec<Alt>> = rules
+            .into_iter()
+            .map(|(k, alts)| {
+                let alts = alts
+                    .into_iter()
+                    .map(|syms| {
+                        let children: Vec<String> = syms
+                            .iter()
+                            .filter_map(|s| match s {
+                                Symbol::NonTerminal(t) => Some(t.clone()),
+                                _ => None,
+                            })
+                            .collect();
+                        Alt {
+                            base: metric.base(&syms),
+                            suffix: vec![vec![]; children.len() + 1],
+                            children,
+                            syms,
+                        }
+                    })
+                    .collect();
+                (k, alts)
+            })
+            .collect();
+        let total = rules.keys().map(|k| (k.clone(), vec![])).collect();
+        let mut c = Counts {
+            metric,
+            max,
+            rules,
+            total,
+        };
+        for n in 0..=max {
+            c.fill_level(n)?;
+        }
+        Ok(c)
+    }
+
+    // level n may depend on itself through alternatives of size 0, iterate to a fixpoint.
+    // Counts only grow, so one that still changes after every nonterminal had a
+    // chance to propagate is infinite.
+    fn fill_level(&mut self, n: usize) -> Result<(), String> {
+        for t in self.total.values_mut() {
+            t.push(BigUint::zero());
+        }
+        for alt in self.rules.values_mut().flatten() {
+            let m = alt.children.len();
+            for (i, s) in alt.suffix.iter_mut().enumerate() {
+                s.push(if i == m && n == 0 {
+                    BigUint::one()
+                } else {
+                    BigUint::zero()
+                });
+            }
+        }
+        for _ in 0..self.rules.len() + 2 {
+            for alt in self.rules.values_mut().flatten() {
+                for i in (0..alt.children.len()).rev() {
+                    let child = &self.total[&alt.children[i]];
+                    let mut s = BigUint::zero();
+                    for (j, cj) in child.iter().enumerate() {
+                        s += &(cj * &alt.suffix[i + 1][n - j]);
+                    }
+                    alt.suffix[i][n] = s;
+                }
+            }
+            let mut changed = false;
+            for (k, alts) in &self.rules {
+                let mut c = BigUint::zero();
+                for alt in alts.iter().filter(|a| a.base <= n) {
+                    c += &alt.suffix[0][n - alt.base];
+                }
+                let t = self.total.get_mut(k).unwrap();
+                if t[n] != c {
+                    t[n] = c;
+                    changed = true;
+                }
+            }
+            if !changed {
+                return Ok(());
+            }
+        }
+        Err(format!(
+            "infinitely many derivations of size {}, some rule derives itself without growing",
+            n
+        ))
+    }
+
+    pub fn count(&self, bnf: &str, n: usize) -> BigUint {
+        self.total
+            .get(bnf)
+            .and_then(|t| t.get(n))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    // a derivation of bnf with size n, every one of them equally likely
+    pub fn sample<R: Rng + ?Sized>(
+        &self,
+        bnf: &str,
+        n: usize,
+        rng: &mut R,
+    ) -> Result<Derivation, String> {
+        if !self.rules.contains_key(bnf) {
+            return Err(format!("No production rule for {}", bnf));
+        }
+        if n > self.max {
+            return Err(format!("size {} is beyond the counted {}", n, self.max));
+        }
+        if self.count(bnf, n).is_zero() {
+            return Err(format!("{} has no derivation of size {}", bnf, n));
+        }
+        // a uniform rank is a uniform derivation
+        let k = BigUint::random_below(&self.total[bnf][n], rng);
+        self.unrank_exact(bnf, n, &k)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn collection(bnfs: &[&str]) -> Collection {
+        let mut c = Collection::new();
+        bnfs.iter().for_each(|b| c.add(b).unwrap());
+        c
+    }
+
+    #[test]
+    fn catalan() {
+        let c = collection(&[r#"<t>::=<t><t>|"x""#]);
+        let counts = c.counts("<t>", SizeMetric::Text, 30).unwrap();
+        assert_eq!(counts.count("<t>", 5).to_string(), "14");
+        assert_eq!(counts.count("<t>", 30).to_string(), "1002242216651368");
+        let nodes = c.counts("<t>", SizeMetric::Nodes, 9).unwrap();
+        assert_eq!(nodes.count("<t>", 9).to_string(), "14");
+    }
+
+    #[test]
+    fn infinite_counts() {
+        let c = collection(&[r#"<a>::=<a>|"x""#]);
+        assert!(c.counts("<a>", SizeMetric::Text, 3).is_err());
+        assert!(c.counts("<a>", SizeMetric::Nodes, 3).is_ok());
+    }
+
+    #[test]
+    fn all_strings_sampled() {
+        let c = collection(&[r#"<b>::=<b>"0"|<b>"1"|E"#]);
+        let counts = c.counts("<b>", SizeMetric::Text, 3).unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut seen = HashMap::new();
+        for _ in 0..800 {
+            let s = counts.sample("<b>", 3, &mut rng).unwrap().text();
+            *seen.entry(s).or_insert(0) += 1;
+        }
+        assert_eq!(seen.len(), 8);
+        assert!(seen.values().all(|&n| n > 60 && n < 140));
+    }
+
+    #[test]
+    fn values_not_counted() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let c = collection(&[r#"<o>::=<@int(1,5)>"-"<@int(1,5)>"#]);
+        assert!(c.gen_uniform("<o>", 3, SizeMetric::Text, &mut rng).is_err());
+        assert!(c.count("<o>", 3).is_err());
+        let c = collection(&[r#"<o>::=@upper(<w>)"#, r#"<w>::="a"<w>|"b""#]);
+        assert!(c.gen_uniform("<o>", 3, SizeMetric::Text, &mut rng).is_err());
+        // a reference repeats its binding, a fresh copy would be counted
+        let c = collection(&[r#"<o>::=<t as v>"-"$v"#, r#"<t>::="a"<t>|"b""#]);
+        assert!(c.gen_uniform("<o>", 7, SizeMetric::Text, &mut rng).is_err());
+        assert!(c.unrank("<o>", 7, &BigUint::zero()).is_err());
+        let c = collection(&[r#"<o>::=<@len(<d>)>" "<d>"#, r#"<d>::="x"<d>|E"#]);
+        assert!(c.gen_uniform("<o>", 3, SizeMetric::Text, &mut rng).is_err());
+        // ambiguity still searches the shapes
+        assert!(c.ambiguity("<o>", 3).is_ok());
+        // rules out of reach do not matter
+        let c = collection(&[r#"<o>::="x"<o>|E"#, "<p>::=<@int(1,5)>"]);
+        assert_eq!(c.count("<o>", 3).unwrap().to_string(), "4");
+        assert!(c.count("<p>", 3).is_err());
+    }
+}
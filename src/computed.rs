@@ -0,0 +1,273 @@
+// computed fields, written <@len(<payload>,u16be)> in mBNF
+//
+//   <@len(<x>[,enc])>            length of <x> in bytes
+//   <@count(<x>,<item>[,enc])>   expansions of <item> in <x>, not counting nested ones
+//   <@crc32(<x>[,enc])>          CRC-32 (IEEE) of <x>
+//   <@adler32(<x>[,enc])>        Adler-32 of <x>
+//
+// <x> is a nonterminal of the same alternative, before or after the field, or $v for
+// the symbol bound with "as v". enc is dec (the default of len and count), hex (the
+// default of the checksums, 8 digits for them) or a binary integer u8, u16be, u16le,
+// u32be, u32le, u64be or u64le, one char U+0000..U+00FF per byte. A value too large
+// for its width is an error.
+//
+// Generation leaves a placeholder and fills it in once the rule expansion holding
+// the field is complete. Text is measured as it is written: as UTF-8, or in binary
+// output with chars below U+0100 as one byte.
+use crate::builtin::split_args;
+use crate::codec::to_bytes;
+use crate::collection::Rules;
+use crate::parser::Symbol;
+use crate::regex::Regex;
+
+const NAMES: [&str; 4] = ["len", "count", "crc32", "adler32"];
+
+// the nonterminal deriving any byte
+const BYTE: &str = "<@byte>";
+
+pub enum Target {
+    Symbol(String), // "<name>"
+    Var(String),
+}
+
+enum Calc {
+    Len,
+    Count(String), // "<item>"
+    Crc32,
+    Adler32,
+}
+
+enum Enc {
+    Dec,
+    Hex,
+    Bin { width: usize, big: bool },
+}
+
+pub struct Computed {
+    pub source: String,
+    pub target: Target,
+    calc: Calc,
+    enc: Enc,
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for b in data {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for x in chunk {
+            a += *x as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+fn symbol(arg: &str) -> Result<String, String> {
+    match arg.starts_with('<') && arg.ends_with('>') && arg.len() > 2 {
+        true => Ok(arg.to_string()),
+        false => Err(format!("{} is not a nonterminal", arg)),
+    }
+}
+
+impl Computed {
+    // whether source names a computed field rather than a built-in
+    pub fn is_computed(source: &str) -> bool {
+        source
+            .split_once('(')
+            .is_some_and(|(name, _)| NAMES.contains(&name))
+    }
+
+    pub fn new(source: &str) -> Result<Self, String> {
+        let (name, args) = source
+            .split_once('(')
+            .and_then(|(name, rest)| Some((name, split_args(rest.strip_suffix(')')?))))
+            .ok_or_else(|| format!("unclosed arguments in <@{}>", source))?;
+        let target = match args.first() {
+            Some(var) if var.starts_with('$') => Target::Var(var[1..].to_string()),
+            Some(arg) => Target::Symbol(symbol(arg)?),
+            None => return Err(format!("<@{}> needs a nonterminal", source)),
+        };
+        let (calc, rest) = match name {
+            "count" => match args.get(1) {
+                Some(item) => (Calc::Count(symbol(item)?), &args[2..]),
+                None => return Err(format!("<@{}> needs the item to count", source)),
+            },
+            "len" => (Calc::Len, &args[1..]),
+            "crc32" => (Calc::Crc32, &args[1..]),
+            _ => (Calc::Adler32, &args[1..]),
+        };
+        let enc = match rest {
+            [] if matches!(calc, Calc::Crc32 | Calc::Adler32) => Enc::Hex,
+            [] => Enc::Dec,
+            [enc] => match enc.as_str() {
+                "dec" => Enc::Dec,
+                "hex" => Enc::Hex,
+                "u8" => Enc::Bin {
+                    width: 1,
+                    big: true,
+                },
+                e => {
+                    let (bits, order) = e
+                        .strip_prefix('u')
+                        .filter(|e| e.len() > 2)
+                        .map(|e| e.split_at(e.len() - 2))
+                        .ok_or_else(|| format!("unknown encoding {}", e))?;
+                    match (bits, order) {
+                        ("16" | "32" | "64", "be" | "le") => Enc::Bin {
+                            width: bits.parse::<usize>().unwrap() / 8,
+                            big: order == "be",
+                        },
+                        _ => return Err(format!("unknown encoding {}", e)),
+                    }
+                }
+            },
+            _ => return Err(format!("too many arguments in <@{}>", source)),
+        };
+        Ok(Computed {
+            source: source.to_string(),
+            target,
+            calc,
+            enc,
+        })
+    }
+
+    pub fn key(&self) -> String {
+        format!("<@{}>", self.source)
+    }
+
+    // the nonterminal a count counts
+    pub fn item(&self) -> Option<&str> {
+        match &self.calc {
+            Calc::Count(item) => Some(item),
+            _ => None,
+        }
+    }
+
+    // the field for the text of the target, count is the number of items in it
+    pub fn value(&self, text: &str, count: usize, binary: bool) -> Result<String, String> {
+        let bytes = match binary {
+            true => to_bytes(text),
+            false => text.as_bytes().to_vec(),
+        };
+        let (n, checksum) = match self.calc {
+            Calc::Len => (bytes.len() as u64, false),
+            Calc::Count(_) => (count as u64, false),
+            Calc::Crc32 => (crc32(&bytes) as u64, true),
+            Calc::Adler32 => (adler32(&bytes) as u64, true),
+        };
+        Ok(match self.enc {
+            Enc::Dec => n.to_string(),
+            Enc::Hex if checksum => format!("{:08x}", n),
+            Enc::Hex => format!("{:x}", n),
+            Enc::Bin { width, big } => {
+                if width < 8 && n >> (8 * width) != 0 {
+                    return Err(format!("{} does not fit {}", n, self.key()));
+                }
+                let mut bytes = n.to_be_bytes()[8 - width..].to_vec();
+                if !big {
+                    bytes.reverse();
+                }
+                bytes.into_iter().map(char::from).collect()
+            }
+        })
+    }
+
+    // text size gen is tuned for, a length or count is taken to have two digits
+    pub fn expected_len(&self) -> f64 {
+        match self.enc {
+            Enc::Bin { width, .. } => width as f64,
+            Enc::Hex if matches!(self.calc, Calc::Crc32 | Calc::Adler32) => 8.0,
+            Enc::Dec | Enc::Hex => 2.0,
+        }
+    }
+
+    // rules deriving every value of the field
+    pub fn rules(&self) -> Rules {
+        let pattern = match self.enc {
+            Enc::Dec => "[0-9]+",
+            Enc::Hex if matches!(self.calc, Calc::Crc32 | Calc::Adler32) => "[0-9a-f]{8}",
+            Enc::Hex => "[0-9a-f]+",
+            Enc::Bin { width, .. } => {
+                let bytes = (0..=255u8)
+                    .map(|b| vec![Symbol::Terminal(char::from(b).to_string())])
+                    .collect();
+                let field = vec![Symbol::NonTerminal(BYTE.to_string()); width];
+                return Rules::from([(self.key(), vec![field]), (BYTE.to_string(), bytes)]);
+            }
+        };
+        let shape = Regex::new(pattern).unwrap();
+        let mut rules = Rules::from([(self.key(), vec![vec![Symbol::NonTerminal(shape.key())]])]);
+        rules.extend(shape.rules());
+        rules
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collection::Collection;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn fields_filled_in() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+        let mut c = Collection::new();
+        c.add(r#"<msg>::=<@len(<body>,u16be)><@count(<body>,<word>)>" "<body>" "<@crc32(<body>)>"#)
+            .unwrap();
+        c.add(r#"<body>::=<word>" "<body>|<word>"#).unwrap();
+        c.add(r#"<word>::=/[a-z]{1,5}/|<@len($w,hex) as x>"-"<word as w>"#)
+            .unwrap_err();
+        c.add(r#"<word>::=/[a-z]{1,5}/|<@len($w,hex)>"-"<word as w>"#)
+            .unwrap();
+        let mut rng = StdRng::seed_from_u64(5);
+        for _ in 0..30 {
+            let (text, choices) = c.gen_recorded("<msg>", &mut rng).unwrap();
+            let bytes = to_bytes(&text);
+            let (len, rest) = bytes.split_at(2);
+            let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+            let rest = String::from_utf8(rest.to_vec()).unwrap();
+            let (count, rest) = rest.split_once(' ').unwrap();
+            let (body, crc) = rest.rsplit_once(' ').unwrap();
+            assert_eq!(len, body.len(), "{:?}", text);
+            assert_eq!(count.parse::<usize>().unwrap(), body.split(' ').count());
+            assert_eq!(crc, format!("{:08x}", crc32(body.as_bytes())));
+            // a nested word is prefixed with its length in hex
+            for word in body.split(' ') {
+                if let Some((n, w)) = word.split_once('-') {
+                    assert_eq!(usize::from_str_radix(n, 16).unwrap(), w.len(), "{}", word);
+                }
+            }
+            assert!(c.accepts("<msg>", &text).unwrap());
+            assert_eq!(c.replay("<msg>", &choices).unwrap(), text);
+        }
+        assert!(c.add("<x>::=<@len(<y>)>").is_err());
+        assert!(c.add("<x>::=<@len(<word>,u24be)><word>").is_err());
+        c.add("<x>::=<@len(<body>,u8)><body>").unwrap();
+        c.add(r#"<body>::="a"<body>|"a""#).unwrap();
+        assert!(c.accepts("<x>", "\u{5}aaaaa").unwrap());
+        // é is two bytes of text but one of binary output
+        c.add("<l>::=<@len(<e>)>\"-\"<e>").unwrap();
+        c.add("<e>::=0xe90xe9").unwrap();
+        assert_eq!(c.gen_with_rng("<l>", &mut rng).unwrap(), "4-éé");
+        assert_eq!(
+            c.gen_bytes_with_rng("<l>", &mut rng).unwrap(),
+            b"2-\xe9\xe9"
+        );
+        c.set_binary(true);
+        assert_eq!(c.gen_with_rng("<l>", &mut rng).unwrap(), "2-éé");
+    }
+}
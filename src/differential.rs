@@ -0,0 +1,114 @@
+// differential testing of two implementations of one format
+//
+// Both programs get the same input; they agree when they exit the same way and
+// print the same stdout, after the chosen normalisations.
+use crate::exec::Outcome;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Normalize {
+    Trim,  // leading and trailing whitespace of every line, and empty lines at the end
+    Space, // runs of whitespace become one space
+    Case,  // ascii lowercase
+    Sort,  // lines in sorted order
+}
+
+impl FromStr for Normalize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "trim" => Ok(Normalize::Trim),
+            "space" => Ok(Normalize::Space),
+            "case" => Ok(Normalize::Case),
+            "sort" => Ok(Normalize::Sort),
+            _ => Err(format!("unknown normalisation {}", s)),
+        }
+    }
+}
+
+pub fn normalize(out: &[u8], how: &[Normalize]) -> String {
+    let mut s = String::from_utf8_lossy(out).into_owned();
+    for n in how {
+        s = match n {
+            Normalize::Trim => {
+                let lines: Vec<&str> = s.lines().map(str::trim).collect();
+                lines.join("\n").trim_end_matches('\n').to_string()
+            }
+            Normalize::Space => s.split_whitespace().collect::<Vec<_>>().join(" "),
+            Normalize::Case => s.to_ascii_lowercase(),
+            Normalize::Sort => {
+                let mut lines: Vec<&str> = s.lines().collect();
+                lines.sort();
+                lines.join("\n")
+            }
+        };
+    }
+    s
+}
+
+// how two runs on one input differ
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Difference {
+    Status(String, String),
+    Stdout { offset: usize }, // first differing byte of the normalised output
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Difference::Status(l, r) => write!(f, "{} vs {}", l, r),
+            Difference::Stdout { offset } => write!(f, "stdout differs at byte {}", offset),
+        }
+    }
+}
+
+pub fn compare(left: &Outcome, right: &Outcome, how: &[Normalize]) -> Option<Difference> {
+    if left.status() != right.status() {
+        return Some(Difference::Status(left.status(), right.status()));
+    }
+    let (l, r) = (normalize(&left.stdout, how), normalize(&right.stdout, how));
+    let offset = l
+        .bytes()
+        .zip(r.bytes())
+        .position(|(a, b)| a != b)
+        .or_else(|| (l.len() != r.len()).then_some(l.len().min(r.len())))?;
+    Some(Difference::Stdout { offset })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcome(code: i32, stdout: &str) -> Outcome {
+        Outcome {
+            code: Some(code),
+            signal: None,
+            timed_out: false,
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: vec![],
+        }
+    }
+
+    #[test]
+    fn compare_normalised() {
+        let (a, b) = (outcome(0, "B 1\n a  2 \n\n"), outcome(0, "a 2\nb 1"));
+        assert_eq!(compare(&a, &b, &[]), Some(Difference::Stdout { offset: 0 }));
+        let how = [Normalize::Trim, Normalize::Case, Normalize::Sort];
+        assert_eq!(
+            compare(&a, &b, &how),
+            Some(Difference::Stdout { offset: 2 })
+        );
+        let how = [Normalize::Space, Normalize::Case];
+        assert_eq!(normalize(&a.stdout, &how), "b 1 a 2");
+        let how = [Normalize::Trim, Normalize::Case, Normalize::Sort];
+        let b = outcome(0, "a  2\nb 1");
+        assert_eq!(compare(&a, &b, &how), None);
+        let c = outcome(1, "a 2\nb 1");
+        assert_eq!(
+            compare(&b, &c, &how).unwrap().to_string(),
+            "exit 0 vs exit 1"
+        );
+    }
+}
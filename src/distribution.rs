@@ -0,0 +1,244 @@
+// numbers drawn from a distribution, the keyword form of <@int(...)> and <@float(...)>
+//
+//   dist=uniform      min, max
+//   dist=normal       mean (0), sd (1)
+//   dist=exponential  rate (1)
+//   dist=lognormal    mu (0), sigma (1), of the underlying normal
+//   dist=zipf         n, s (1): rank k in 1..=n with weight 1/k^s
+//
+// min and max also clamp the other distributions; digits sets the decimals of a
+// float (6 by default) and pad zero pads to a width. The rng is the one generation
+// draws from, so seeded runs stay reproducible.
+use crate::builtin::keyword;
+use rand::{Rng, RngCore};
+use std::f64::consts::PI;
+
+const MAX_ZIPF: usize = 1_000_000;
+
+enum Dist {
+    Uniform { lo: f64, hi: f64 },
+    Normal { mean: f64, sd: f64 },
+    Exponential { rate: f64 },
+    LogNormal { mu: f64, sigma: f64 },
+    Zipf { cumulative: Vec<f64> },
+}
+
+pub struct Numbers {
+    dist: Dist,
+    int: bool,
+    min: Option<f64>,
+    max: Option<f64>,
+    digits: usize,
+    pad: usize,
+}
+
+fn standard_normal(rng: &mut dyn RngCore) -> f64 {
+    let u1 = 1.0 - rng.gen::<f64>();
+    let u2 = rng.gen::<f64>();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+}
+
+impl Numbers {
+    // args are all key=value
+    pub fn parse(int: bool, args: &[&str]) -> Result<Self, String> {
+        let mut pairs = vec![];
+        for arg in args {
+            let (k, v) = keyword(arg).ok_or_else(|| format!("{} is not key=value", arg))?;
+            pairs.push((k, v));
+        }
+        let text = |key: &str| pairs.iter().find(|(k, _)| *k == key).map(|(_, v)| *v);
+        let num = |key: &str| -> Result<Option<f64>, String> {
+            text(key)
+                .map(|v| {
+                    v.parse::<f64>()
+                        .ok()
+                        .filter(|x| x.is_finite())
+                        .ok_or_else(|| format!("{}={} is not a number", key, v))
+                })
+                .transpose()
+        };
+        let or = |key: &str, default: f64| num(key).map(|x| x.unwrap_or(default));
+        let (min, max) = (num("min")?, num("max")?);
+        let dist_name = text("dist").unwrap_or("uniform");
+        let (dist, keys): (Dist, &[&str]) = match dist_name {
+            "uniform" => match (min, max) {
+                (Some(lo), Some(hi)) => (Dist::Uniform { lo, hi }, &[]),
+                _ => return Err("dist=uniform needs min and max".to_string()),
+            },
+            "normal" => (
+                Dist::Normal {
+                    mean: or("mean", 0.0)?,
+                    sd: or("sd", 1.0)?,
+                },
+                &["mean", "sd"],
+            ),
+            "exponential" => (
+                Dist::Exponential {
+                    rate: or("rate", 1.0)?,
+                },
+                &["rate"],
+            ),
+            "lognormal" => (
+                Dist::LogNormal {
+                    mu: or("mu", 0.0)?,
+                    sigma: or("sigma", 1.0)?,
+                },
+                &["mu", "sigma"],
+            ),
+            "zipf" => {
+                let n = num("n")?.ok_or("dist=zipf needs n")?;
+                if !(1.0..=MAX_ZIPF as f64).contains(&n) || n.fract() != 0.0 {
+                    return Err(format!("zipf n must be a whole number in 1..={}", MAX_ZIPF));
+                }
+                let s = or("s", 1.0)?;
+                let mut total = 0.0;
+                let cumulative = (1..=n as usize)
+                    .map(|k| {
+                        total += (k as f64).powf(-s);
+                        total
+                    })
+                    .collect();
+                (Dist::Zipf { cumulative }, &["n", "s"])
+            }
+            d => return Err(format!("unknown distribution {}", d)),
+        };
+        for (k, _) in &pairs {
+            if !["dist", "min", "max", "digits", "pad"].contains(k) && !keys.contains(k) {
+                return Err(format!("{} does not apply to dist={}", k, dist_name));
+            }
+        }
+        let bad = match &dist {
+            Dist::Uniform { lo, hi } => {
+                int && lo.ceil() > hi.floor() || !int && !(hi - lo).is_finite()
+            }
+            Dist::Normal { sd, .. } => *sd < 0.0,
+            Dist::Exponential { rate } => *rate <= 0.0,
+            Dist::LogNormal { sigma, .. } => *sigma < 0.0,
+            _ => false,
+        };
+        if bad || min.zip(max).is_some_and(|(lo, hi)| lo > hi) {
+            return Err(format!("bad parameters for dist={}", dist_name));
+        }
+        let count = |key: &str, default: usize| {
+            text(key).map_or(Ok(default), |v| {
+                v.parse()
+                    .map_err(|_| format!("{}={} is not a count", key, v))
+            })
+        };
+        Ok(Numbers {
+            dist,
+            int,
+            min,
+            max,
+            digits: if int { 0 } else { count("digits", 6)? },
+            pad: count("pad", 0)?,
+        })
+    }
+
+    pub fn sample(&self, rng: &mut dyn RngCore) -> String {
+        let x = match &self.dist {
+            Dist::Uniform { lo, hi } if self.int => {
+                rng.gen_range(lo.ceil() as i64..=hi.floor() as i64) as f64
+            }
+            Dist::Uniform { lo, hi } => lo + (hi - lo) * rng.gen::<f64>(),
+            Dist::Normal { mean, sd } => mean + sd * standard_normal(rng),
+            Dist::Exponential { rate } => -(1.0 - rng.gen::<f64>()).ln() / rate,
+            Dist::LogNormal { mu, sigma } => (mu + sigma * standard_normal(rng)).exp(),
+            Dist::Zipf { cumulative } => {
+                let u = rng.gen::<f64>() * cumulative.last().unwrap();
+                (cumulative
+                    .partition_point(|&c| c <= u)
+                    .min(cumulative.len() - 1)
+                    + 1) as f64
+            }
+        };
+        let x = x
+            .max(self.min.unwrap_or(f64::MIN))
+            .min(self.max.unwrap_or(f64::MAX));
+        match self.int {
+            true => format!("{:0w$}", x.round() as i64, w = self.pad),
+            false => format!("{:0w$.d$}", x, w = self.pad, d = self.digits),
+        }
+    }
+
+    // a regex matching every sample
+    pub fn shape(&self) -> String {
+        let nonnegative = match self.dist {
+            Dist::Exponential { .. } | Dist::LogNormal { .. } | Dist::Zipf { .. } => true,
+            _ => self.min.is_some_and(|m| m >= 0.0),
+        };
+        let sign = if nonnegative { "" } else { "-?" };
+        match self.digits {
+            0 => format!("{}[0-9]+", sign),
+            d => format!("{}[0-9]+\\.[0-9]{{{}}}", sign, d),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn is_match(pattern: &str, s: &str) -> bool {
+        crate::regex::Regex::new(pattern).unwrap().is_match(s)
+    }
+
+    fn draw(int: bool, args: &[&str], n: usize) -> (Numbers, Vec<String>) {
+        let numbers = Numbers::parse(int, args).unwrap();
+        let mut rng = StdRng::seed_from_u64(8);
+        let out = (0..n).map(|_| numbers.sample(&mut rng)).collect();
+        (numbers, out)
+    }
+
+    #[test]
+    fn distributions() {
+        let (normal, out) = draw(
+            false,
+            &["dist=normal", "mean=50", "sd=10", "digits=2"],
+            2000,
+        );
+        let xs: Vec<f64> = out.iter().map(|s| s.parse().unwrap()).collect();
+        let mean = xs.iter().sum::<f64>() / xs.len() as f64;
+        let sd = (xs.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / xs.len() as f64).sqrt();
+        assert!(
+            (mean - 50.0).abs() < 1.0 && (sd - 10.0).abs() < 1.0,
+            "{} {}",
+            mean,
+            sd
+        );
+        assert!(out.iter().all(|s| is_match(&normal.shape(), s)));
+        let (zipf, out) = draw(true, &["dist=zipf", "n=1000", "s=1.1", "pad=4"], 2000);
+        let ones = out.iter().filter(|s| *s == "0001").count();
+        let twos = out.iter().filter(|s| *s == "0002").count();
+        assert!(ones > twos && twos > 0, "{} {}", ones, twos);
+        assert!(out
+            .iter()
+            .all(|s| is_match(&zipf.shape(), s) && s.len() >= 4));
+        let (_, out) = draw(
+            false,
+            &["dist=exponential", "rate=2", "max=1", "digits=1"],
+            500,
+        );
+        assert!(out
+            .iter()
+            .all(|s| (0.0..=1.0).contains(&s.parse::<f64>().unwrap())));
+        let (_, out) = draw(true, &["dist=lognormal", "min=3", "max=9"], 500);
+        assert!(out
+            .iter()
+            .all(|s| (3..=9).contains(&s.parse::<i64>().unwrap())));
+        for bad in [
+            &["dist=zipf"][..],
+            &["dist=normal", "sd=-1"],
+            &["dist=normal", "rate=1"],
+            &["dist=uniform", "min=1"],
+            &["dist=cauchy"],
+            &["dist=normal", "1"],
+        ] {
+            assert!(Numbers::parse(true, bad).is_err(), "{:?}", bad);
+        }
+        let wide = ["dist=uniform", "min=-1e308", "max=1e308"];
+        assert!(Numbers::parse(false, &wide).is_err());
+    }
+}
@@ -0,0 +1,207 @@
+// running a program under test on one input
+//
+// An argument containing @@ gets the path of a temporary file holding the input,
+// as in AFL; without one the input is written to stdin. On unix the program runs
+// in its own process group, which a timeout kills whole.
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+static TEMP: AtomicUsize = AtomicUsize::new(0);
+
+pub struct Target {
+    argv: Vec<String>,
+    pub timeout: Option<Duration>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Outcome {
+    pub code: Option<i32>,   // exit code, None when killed by a signal or timed out
+    pub signal: Option<i32>, // terminating signal, unix only
+    pub timed_out: bool,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl Outcome {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+
+    // a short description of how the program ended
+    pub fn status(&self) -> String {
+        match (self.timed_out, self.signal, self.code) {
+            (true, _, _) => "timeout".to_string(),
+            (_, Some(s), _) => format!("signal {}", s),
+            (_, _, Some(c)) => format!("exit {}", c),
+            _ => "unknown".to_string(),
+        }
+    }
+}
+
+impl Target {
+    pub fn new(argv: Vec<String>) -> Result<Self, String> {
+        if argv.is_empty() {
+            return Err("no command to run".to_string());
+        }
+        Ok(Target {
+            argv,
+            timeout: None,
+        })
+    }
+
+    // a command line run by sh
+    pub fn shell(cmd: &str) -> Self {
+        Target {
+            argv: vec!["sh".to_string(), "-c".to_string(), cmd.to_string()],
+            timeout: None,
+        }
+    }
+
+    pub fn run(&self, input: &[u8]) -> Result<Outcome, String> {
+        let file = self.argv.iter().any(|a| a.contains("@@")).then(|| {
+            std::env::temp_dir().join(format!(
+                "datarobot-{}-{}",
+                std::process::id(),
+                TEMP.fetch_add(1, Ordering::Relaxed)
+            ))
+        });
+        if let Some(f) = &file {
+            fs::write(f, input).map_err(|e| format!("{}: {}", f.display(), e))?;
+        }
+        let outcome = self.spawn(input, &file);
+        if let Some(f) = &file {
+            let _ = fs::remove_file(f);
+        }
+        outcome.map_err(|e| format!("{}: {}", self.argv[0], e))
+    }
+
+    fn spawn(&self, input: &[u8], file: &Option<PathBuf>) -> io::Result<Outcome> {
+        let args: Vec<String> = self.argv[1..]
+            .iter()
+            .map(|a| match file {
+                Some(f) => a.replace("@@", &f.to_string_lossy()),
+                None => a.clone(),
+            })
+            .collect();
+        let mut command = Command::new(&self.argv[0]);
+        command
+            .args(&args)
+            .stdin(match file {
+                Some(_) => Stdio::null(),
+                None => Stdio::piped(),
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        let mut child = command.spawn()?;
+        // feed and drain on threads, a full pipe must not block the wait below
+        let stdin = child.stdin.take().map(|mut s| {
+            let input = input.to_vec();
+            thread::spawn(move || {
+                let _ = s.write_all(&input);
+            })
+        });
+        let drain = |r: Option<Box<dyn Read + Send>>| {
+            let (tx, rx) = mpsc::channel();
+            thread::spawn(move || {
+                let mut buf = vec![];
+                if let Some(mut r) = r {
+                    let _ = r.read_to_end(&mut buf);
+                }
+                let _ = tx.send(buf);
+            });
+            rx
+        };
+        let stdout = drain(child.stdout.take().map(|s| Box::new(s) as _));
+        let stderr = drain(child.stderr.take().map(|s| Box::new(s) as _));
+        let timed_out = self.wait(&mut child)?;
+        let status = child.wait()?;
+        if let (false, Some(t)) = (timed_out, stdin) {
+            let _ = t.join();
+        }
+        // children of a killed program may hold the pipes open, keep what arrived
+        let collect = |rx: mpsc::Receiver<Vec<u8>>| match timed_out {
+            true => rx
+                .recv_timeout(Duration::from_millis(100))
+                .unwrap_or_default(),
+            false => rx.recv().unwrap_or_default(),
+        };
+        #[cfg(unix)]
+        let signal = std::os::unix::process::ExitStatusExt::signal(&status);
+        #[cfg(not(unix))]
+        let signal = None;
+        Ok(Outcome {
+            code: if timed_out { None } else { status.code() },
+            signal: if timed_out { None } else { signal },
+            timed_out,
+            stdout: collect(stdout),
+            stderr: collect(stderr),
+        })
+    }
+
+    // true when the child had to be killed
+    fn wait(&self, child: &mut Child) -> io::Result<bool> {
+        let timeout = match self.timeout {
+            Some(t) => t,
+            None => return Ok(false),
+        };
+        let start = Instant::now();
+        let mut pause = Duration::from_millis(1);
+        while child.try_wait()?.is_none() {
+            if start.elapsed() >= timeout {
+                // the group of the child has its pid, a shell's children go too
+                #[cfg(unix)]
+                unsafe {
+                    libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+                }
+                child.kill()?;
+                return Ok(true);
+            }
+            thread::sleep(pause);
+            pause = (pause * 2).min(Duration::from_millis(20));
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stdin_and_file() {
+        let out = Target::shell("tr a b").run(b"banana").unwrap();
+        assert_eq!((out.success(), out.stdout), (true, b"bbnbnb".to_vec()));
+        let out = Target::shell("cat @@ >&2; exit 3").run(b"x").unwrap();
+        assert_eq!((out.code, out.stderr), (Some(3), b"x".to_vec()));
+    }
+
+    #[test]
+    fn timeout_and_signal() {
+        let mut t = Target::shell("sleep 5");
+        t.timeout = Some(Duration::from_millis(50));
+        assert_eq!(t.run(b"").unwrap().status(), "timeout");
+        let out = Target::shell("kill -SEGV $$").run(b"").unwrap();
+        assert_eq!(out.status(), "signal 11");
+        // the children of sh are killed along with it
+        t = Target::shell("sleep 5 & echo $!; wait");
+        t.timeout = Some(Duration::from_millis(200));
+        let out = t.run(b"").unwrap();
+        let pid = String::from_utf8(out.stdout).unwrap();
+        assert!(pid.trim().parse::<u32>().is_ok(), "{:?}", pid);
+        // gone, or a zombie nobody reaps
+        let alive = format!("ps -o stat= -p {} | grep -v Z", pid.trim());
+        let gone = (0..100).any(|_| {
+            thread::sleep(Duration::from_millis(10));
+            !Target::shell(&alive).run(b"").unwrap().success()
+        });
+        assert!(gone, "sleep {} outlived the timeout", pid.trim());
+    }
+}
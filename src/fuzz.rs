@@ -0,0 +1,193 @@
+// findings of a fuzzing run
+//
+// A finding is a run of the target that crashed, timed out or exited non-zero.
+// Findings with the same signature, how the target ended plus the start of its
+// stderr with numbers and addresses masked, count as one bug.
+use crate::exec::Outcome;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Kind {
+    Crash,
+    Timeout,
+    Exit,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Kind::Crash => "crash",
+            Kind::Timeout => "timeout",
+            Kind::Exit => "exit",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+const SIGNATURE_LINES: usize = 3;
+
+pub fn classify(outcome: &Outcome) -> Option<Kind> {
+    if outcome.timed_out {
+        Some(Kind::Timeout)
+    } else if outcome.signal.is_some() {
+        Some(Kind::Crash)
+    } else if !outcome.success() {
+        Some(Kind::Exit)
+    } else {
+        None
+    }
+}
+
+pub fn signature(outcome: &Outcome) -> String {
+    let stderr = String::from_utf8_lossy(&outcome.stderr);
+    let mut sig = outcome.status();
+    for line in stderr
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .take(SIGNATURE_LINES)
+    {
+        sig.push('\n');
+        sig += &mask(line);
+    }
+    sig
+}
+
+// decimal numbers and 0x hex addresses become N, they differ between runs of one bug
+fn mask(line: &str) -> String {
+    let mut out = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_digit() {
+            let mut ahead = chars.clone();
+            let hex = c == '0'
+                && ahead.next() == Some('x')
+                && ahead.next().is_some_and(|c| c.is_ascii_hexdigit());
+            if hex {
+                chars.next();
+            }
+            while chars
+                .peek()
+                .is_some_and(|c| c.is_ascii_digit() || hex && c.is_ascii_hexdigit())
+            {
+                chars.next();
+            }
+            out.push('N');
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+// unique findings saved as <dir>/<kind>-<n> with the input, plus .seed (how to
+// generate it again, and the signature) and .stderr; n continues after the
+// findings of earlier runs into the same dir
+pub struct Findings {
+    dir: PathBuf,
+    seen: HashMap<String, usize>, // signature -> number of hits
+    next: usize,                  // n of the next finding
+    pub saved: usize,
+}
+
+// n of a file saved as <kind>-<n>, with or without extension
+fn index(file: &str) -> Option<usize> {
+    let (kind, n) = file.split('.').next()?.rsplit_once('-')?;
+    let kinds = [Kind::Crash, Kind::Timeout, Kind::Exit];
+    match kinds.iter().any(|k| k.to_string() == kind) {
+        true => n.parse().ok(),
+        false => None,
+    }
+}
+
+impl Findings {
+    pub fn new(dir: &str) -> Result<Self, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir, e))?;
+        let next = fs::read_dir(dir)
+            .map_err(|e| format!("{}: {}", dir, e))?
+            .filter_map(|entry| index(entry.ok()?.file_name().to_str()?))
+            .max()
+            .map_or(0, |n| n + 1);
+        Ok(Findings {
+            dir: PathBuf::from(dir),
+            seen: HashMap::new(),
+            next,
+            saved: 0,
+        })
+    }
+
+    pub fn hits(&self) -> usize {
+        self.seen.values().sum()
+    }
+
+    // the path the input was saved to, None for a known signature
+    pub fn add(
+        &mut self,
+        kind: Kind,
+        outcome: &Outcome,
+        input: &[u8],
+        seed: &str,
+    ) -> Result<Option<PathBuf>, String> {
+        let sig = signature(outcome);
+        let hits = self.seen.entry(sig.clone()).or_insert(0);
+        *hits += 1;
+        if *hits > 1 {
+            return Ok(None);
+        }
+        let path = self.dir.join(format!("{}-{:04}", kind, self.next));
+        self.next += 1;
+        self.saved += 1;
+        let write = |ext: &str, data: &[u8]| {
+            let p = path.with_extension(ext);
+            fs::write(&p, data).map_err(|e| format!("{}: {}", p.display(), e))
+        };
+        write("", input)?;
+        write("seed", format!("{}\n{}\n", seed, sig).as_bytes())?;
+        write("stderr", &outcome.stderr)?;
+        Ok(Some(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcome(code: i32, stderr: &str) -> Outcome {
+        Outcome {
+            code: Some(code),
+            signal: None,
+            timed_out: false,
+            stdout: vec![],
+            stderr: stderr.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn dedup_by_signature() {
+        let a = outcome(1, "error at 0x7ffd12 in line 12\n");
+        let b = outcome(1, "error at 0x5aa0ff in line 3\n");
+        let c = outcome(2, "error at 0x5aa0ff in line 3\n");
+        assert_eq!(signature(&a), "exit 1\nerror at N in line N");
+        // hex letters only after 0x
+        assert_eq!(mask("line 12abc at 0xfe 0xyz"), "line Nabc at N Nxyz");
+        assert_eq!(signature(&a), signature(&b));
+        assert_ne!(signature(&a), signature(&c));
+        assert_eq!(classify(&outcome(0, "")), None);
+        let dir = std::env::temp_dir().join(format!("datarobot-findings-{}", std::process::id()));
+        let mut f = Findings::new(dir.to_str().unwrap()).unwrap();
+        assert!(f.add(Kind::Exit, &a, b"x", "1").unwrap().is_some());
+        assert!(f.add(Kind::Exit, &b, b"y", "2").unwrap().is_none());
+        let p = f.add(Kind::Exit, &c, b"z", "3").unwrap().unwrap();
+        assert_eq!(fs::read(&p).unwrap(), b"z");
+        assert_eq!((f.saved, f.hits()), (2, 3));
+        // a second run into the same dir keeps the findings of the first
+        let mut f = Findings::new(dir.to_str().unwrap()).unwrap();
+        let p = f.add(Kind::Crash, &a, b"w", "4").unwrap().unwrap();
+        assert!(p.ends_with("crash-0002"));
+        assert_eq!(fs::read(dir.join("exit-0000")).unwrap(), b"x");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,16 @@
+// datarobot: read BNFs and generate text
+pub mod action;
+pub mod bigint;
+pub mod builtin;
+pub mod codec;
+pub mod collection;
+pub mod computed;
+pub mod differential;
+pub mod distribution;
+pub mod exec;
+pub mod fuzz;
+pub mod parser;
+mod preprocessor;
+pub mod regex;
+pub mod transform;
+pub mod wordlist;
@@ -1,29 +1,670 @@
 // Read BNFs and generate text
+use std::env;
 use std::fs;
 use std::io;
 use std::path::Path;
+use std::thread;
+use std::time::Duration;
 
-mod collection;
-mod parser;
-mod preprocessor;
+use datarobot::bigint::BigUint;
+use datarobot::codec;
+use datarobot::collection;
+use datarobot::differential;
+use datarobot::exec::Target;
+use datarobot::fuzz;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+// datarobot [gen] [--grammar <file>] [--start <name>] [-n <count>] [--seed <seed>]
+//           [--threads <threads>] [--size <n>] [--expected-size <n>] [--analyze]
+//           [--bound <n> [--range <from>..<to>] [--ambiguity]] [--record] [--replay <choices>]
+//           [--binary]
+// datarobot check [--grammar <file>] [--start <name>] [--whole] [--binary] [<file>...]
+// datarobot parse [--grammar <file>] [--start <name>] [--whole] [--binary] [<file>...]
+// datarobot reduce [--grammar <file>] [--start <name>] --test <command> [--timeout <s>] <file>
+// datarobot negative [--grammar <file>] [--start <name>] [-n <count>] [--seed <seed>]
+//                    [--errors drop,duplicate,swap,substitute,truncate]
+// datarobot diff [--grammar <file>] [--start <name>] [-n <count>] [--seed <seed>] [--threads <n>]
+//                [--timeout <s>] [--out <dir>] [--normalize trim,space,case,sort] [--binary]
+//                --left <command> --right <command>
+// datarobot fuzz [--grammar <file>] [--start <name>] [-n <count>] [--seed <seed>] [--threads <n>]
+//                [--timeout <s>] [--out <dir>] [--binary] -- <command> [<arg>|@@...]
+//
+// --binary writes samples as bytes, a char below U+0100 as one byte, with nothing
+// around them, and reads inputs as bytes, each file whole. Computed fields measure
+// text the same way, and as UTF-8 without it.
+enum Mode {
+    Gen,
+    Check,    // validate inputs against the grammar
+    Parse,    // check, and print the derivation tree (or parse forest) of accepted inputs
+    Reduce,   // shrink an input while a shell command still exits with 0 on it
+    Fuzz,     // run a command on generated inputs and keep those it fails on
+    Negative, // near miss inputs just outside the language, labelled with the edit made
+    Diff,     // run two commands on generated inputs and keep those they disagree on
+}
+
+struct Options {
+    mode: Mode,
+    grammar: String,
+    start: String,
+    count: Option<usize>,
+    seed: Option<u64>,
+    threads: usize,
+    size: Option<usize>, // sample uniformly among outputs of exactly this length
+    expected_size: Option<f64>,
+    analyze: bool,        // report expected sizes instead of generating
+    bound: Option<usize>, // count derivations up to this length, or list those in range
+    range: Option<(BigUint, BigUint)>,
+    ambiguity: bool, // search the derivations up to bound for an ambiguous output
+    record: bool,    // print the choices behind every sample
+    replay: Option<String>, // choices to generate from, base64 or a JSON array
+    whole: bool,     // check every file as one input instead of line by line
+    binary: bool,    // samples and inputs are bytes, see codec::to_bytes
+    inputs: Vec<String>,
+    test: Option<String>,
+    timeout: Option<Duration>,
+    out: String,          // where fuzz saves findings
+    command: Vec<String>, // program fuzz runs, after --
+    errors: Vec<collection::ErrorKind>,
+    left: Option<String>, // the two commands diff compares
+    right: Option<String>,
+    normalize: Vec<differential::Normalize>,
+}
+
+impl Options {
+    fn parse(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.peekable();
+        let mode = match args.peek().map(String::as_str) {
+            Some("check") => Mode::Check,
+            Some("parse") => Mode::Parse,
+            Some("reduce") => Mode::Reduce,
+            Some("fuzz") => Mode::Fuzz,
+            Some("negative") => Mode::Negative,
+            Some("diff") => Mode::Diff,
+            _ => Mode::Gen,
+        };
+        if matches!(
+            args.peek().map(String::as_str),
+            Some("gen" | "check" | "parse" | "reduce" | "fuzz" | "negative" | "diff")
+        ) {
+            args.next();
+        }
+        let mut opts = Options {
+            mode,
+            grammar: "./bnfs".to_string(),
+            start: "<output>".to_string(),
+            count: None,
+            seed: None,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            size: None,
+            expected_size: None,
+            analyze: false,
+            bound: None,
+            range: None,
+            ambiguity: false,
+            record: false,
+            replay: None,
+            whole: false,
+            binary: false,
+            inputs: vec![],
+            test: None,
+            timeout: None,
+            out: "./findings".to_string(),
+            command: vec![],
+            errors: collection::ALL_ERRORS.to_vec(),
+            left: None,
+            right: None,
+            normalize: vec![],
+        };
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("{} expects a value", arg))
+            };
+            match arg.as_str() {
+                "--grammar" => opts.grammar = value()?,
+                "--start" => opts.start = value()?,
+                "-n" | "--count" => opts.count = Some(parse_num(&value()?)?),
+                "--seed" => opts.seed = Some(parse_num(&value()?)?),
+                "--threads" => opts.threads = parse_num(&value()?)?,
+                "--size" => opts.size = Some(parse_num(&value()?)?),
+                "--expected-size" => opts.expected_size = Some(parse_num(&value()?)?),
+                "--analyze" => opts.analyze = true,
+                "--bound" => opts.bound = Some(parse_num(&value()?)?),
+                "--range" => {
+                    let v = value()?;
+                    let (from, to) = v
+                        .split_once("..")
+                        .ok_or_else(|| format!("range {} is not <from>..<to>", v))?;
+                    opts.range = Some((from.parse()?, to.parse()?));
+                }
+                "--ambiguity" => opts.ambiguity = true,
+                "--record" => opts.record = true,
+                "--replay" => opts.replay = Some(value()?),
+                "--whole" => opts.whole = true,
+                "--binary" => opts.binary = true,
+                "--test" => opts.test = Some(value()?),
+                "--out" => opts.out = value()?,
+                "--left" => opts.left = Some(value()?),
+                "--right" => opts.right = Some(value()?),
+                "--normalize" => {
+                    opts.normalize = value()?
+                        .split(',')
+                        .map(str::parse)
+                        .collect::<Result<_, _>>()?
+                }
+                "--errors" => {
+                    opts.errors = value()?
+                        .split(',')
+                        .map(str::parse)
+                        .collect::<Result<_, _>>()?
+                }
+                "--" => opts.command = args.by_ref().collect(),
+                "--timeout" => opts.timeout = Some(Duration::from_secs_f64(parse_num(&value()?)?)),
+                _ if !matches!(opts.mode, Mode::Gen) && !arg.starts_with('-') => {
+                    opts.inputs.push(arg)
+                }
+                _ => return Err(format!("unknown argument {}", arg)),
+            }
+        }
+        Ok(opts)
+    }
+}
+
+fn parse_num<T: std::str::FromStr>(s: &str) -> Result<T, String> {
+    s.parse().map_err(|_| format!("{} is not a number", s))
+}
 
 fn main() {
+    let opts = match Options::parse(env::args().skip(1)) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
     // File hosts must exist in current path before this produces output
-    if let Ok(lines) = read_bnfs_from_file("./bnfs") {
+    if let Ok(lines) = read_bnfs_from_file(&opts.grammar) {
         let mut a = collection::Collection::new();
         lines.iter().for_each(|l| {
             if let Err(s) = a.add(l) {
                 println!("[skip {}] {}", l, s);
             }
         });
+        if let Some(size) = opts.expected_size {
+            a.set_expected_size(size);
+        }
+        a.set_binary(opts.binary);
+        match opts.mode {
+            Mode::Gen => generate(&a, &opts),
+            Mode::Check | Mode::Parse => std::process::exit(check(&a, &opts)),
+            Mode::Reduce => std::process::exit(reduce(&a, &opts)),
+            Mode::Fuzz => std::process::exit(fuzz(&a, &opts)),
+            Mode::Negative => negative(&a, &opts),
+            Mode::Diff => std::process::exit(diff(&a, &opts)),
+        }
+    }
+}
 
-        vec!["<output>"]
-            .iter()
-            .for_each(|bnf_expr| match a.gen(bnf_expr) {
-                Ok(s) => println!("{}: {}", bnf_expr, s),
+fn generate(a: &collection::Collection, opts: &Options) {
+    let bnf_expr = opts.start.as_str();
+    if opts.analyze {
+        return analyze(a, bnf_expr);
+    }
+    if opts.ambiguity {
+        return match opts.bound {
+            Some(bound) => ambiguity(a, bnf_expr, bound),
+            None => println!("--ambiguity needs --bound"),
+        };
+    }
+    if let Some(bound) = opts.bound {
+        return enumerate(a, bnf_expr, bound, &opts.range);
+    }
+    if let Some(choices) = &opts.replay {
+        let choices = match choices.starts_with('[') {
+            true => collection::Choices::from_json(choices),
+            false => collection::Choices::from_base64(choices),
+        };
+        return match choices.and_then(|c| a.replay(bnf_expr, &c)) {
+            Ok(s) => emit(opts, &s),
+            Err(s) => println!("{}", s),
+        };
+    }
+    if opts.record {
+        let seed = opts.seed.unwrap_or_else(rand::random);
+        eprintln!("seed {}", seed);
+        for i in 0..opts.count.unwrap_or(1) {
+            let mut rng = StdRng::seed_from_u64(collection::sample_seed(seed, i as u64));
+            match a.gen_recorded(bnf_expr, &mut rng) {
+                Ok((s, c)) if opts.binary => {
+                    emit(opts, &s);
+                    eprintln!("choices {}", c.to_base64());
+                }
+                Ok((s, c)) => println!("{}: {}\nchoices {}", bnf_expr, s, c.to_base64()),
+                Err(s) => println!("{}", s),
+            }
+        }
+        return;
+    }
+    match (opts.size, opts.count) {
+        (Some(size), n) => {
+            let seed = opts.seed.unwrap_or_else(rand::random);
+            eprintln!("seed {}", seed);
+            match a.counts(bnf_expr, collection::SizeMetric::Text, size) {
+                Ok(counts) => {
+                    for i in 0..n.unwrap_or(1) {
+                        let mut rng =
+                            StdRng::seed_from_u64(collection::sample_seed(seed, i as u64));
+                        match counts.sample(bnf_expr, size, &mut rng) {
+                            Ok(d) => emit(opts, &d.text()),
+                            Err(s) => println!("{}", s),
+                        }
+                    }
+                }
                 Err(s) => println!("{}", s),
+            }
+        }
+        (None, None) => match a.gen(bnf_expr) {
+            Ok(s) => emit(opts, &s),
+            Err(s) => println!("{}", s),
+        },
+        (None, Some(n)) => {
+            let seed = opts.seed.unwrap_or_else(rand::random);
+            eprintln!("seed {}", seed);
+            for r in a.gen_batch(bnf_expr, seed, n, opts.threads) {
+                match r {
+                    Ok(s) => emit(opts, &s),
+                    Err(s) => println!("{}", s),
+                }
+            }
+        }
+    }
+}
+
+// a sample on stdout, labelled with the start symbol unless binary
+fn emit(opts: &Options, s: &str) {
+    match opts.binary {
+        true => {
+            let mut out = io::stdout().lock();
+            if let Err(e) = io::Write::write_all(&mut out, &codec::to_bytes(s)) {
+                eprintln!("{}", e);
+            }
+        }
+        false => println!("{}: {}", opts.start, s),
+    }
+}
+
+// the input a command gets for a sample
+fn encode(opts: &Options, s: &str) -> Vec<u8> {
+    match opts.binary {
+        true => codec::to_bytes(s),
+        false => s.as_bytes().to_vec(),
+    }
+}
+
+// validate every line (or every whole file) of the inputs, stdin when none given,
+// in parse mode the trees of accepted inputs are printed too,
+// returns the exit status: 0 all accepted, 1 some rejected, 2 on errors
+fn check(a: &collection::Collection, opts: &Options) -> i32 {
+    let r = match a.recognizer() {
+        Ok(r) => r,
+        Err(s) => {
+            eprintln!("{}", s);
+            return 2;
+        }
+    };
+    let inputs = if opts.inputs.is_empty() {
+        vec!["-".to_string()]
+    } else {
+        opts.inputs.clone()
+    };
+    let (mut accepted, mut rejected) = (0, 0);
+    for path in inputs {
+        let content = match read_input(&path, opts.binary) {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                return 2;
+            }
+        };
+        let samples: Vec<(usize, &str)> = if opts.whole || opts.binary {
+            vec![(0, content.as_str())]
+        } else {
+            content.lines().enumerate().collect()
+        };
+        for (line, sample) in samples {
+            match r.check(&opts.start, sample.as_bytes()) {
+                Ok(None) => {
+                    accepted += 1;
+                    if matches!(opts.mode, Mode::Parse) {
+                        println!("{}:{}:", path, line + 1);
+                        match r.parse(&opts.start, sample.as_bytes()) {
+                            Ok(collection::Parse::Tree(d)) => d.display(),
+                            Ok(collection::Parse::Forest(f)) => {
+                                println!("ambiguous, {} nodes", f.nodes.len());
+                                f.display();
+                            }
+                            Err(s) => println!("{}", s),
+                        }
+                    }
+                }
+                Ok(Some(m)) => {
+                    rejected += 1;
+                    let before = &sample.as_bytes()[..m.offset];
+                    let row = line + before.iter().filter(|&&c| c == b'\n').count();
+                    let col = m.offset
+                        - before
+                            .iter()
+                            .rposition(|&c| c == b'\n')
+                            .map_or(0, |p| p + 1);
+                    println!("{}:{}:{}: {}", path, row + 1, col + 1, m);
+                }
+                Err(s) => {
+                    eprintln!("{}", s);
+                    return 2;
+                }
+            }
+        }
+    }
+    eprintln!("{} accepted, {} rejected", accepted, rejected);
+    (rejected > 0) as i32
+}
+
+// the smallest input derivable by replacing subtrees that the test command still accepts
+fn reduce(a: &collection::Collection, opts: &Options) -> i32 {
+    let mut target = match (&opts.test, opts.inputs.as_slice()) {
+        (Some(cmd), [_]) => Target::shell(cmd),
+        _ => {
+            eprintln!("reduce expects --test <command> and one input file");
+            return 2;
+        }
+    };
+    target.timeout = opts.timeout;
+    let path = &opts.inputs[0];
+    let tree = match read_input(path, false)
+        .map_err(|e| format!("{}: {}", path, e))
+        .and_then(|content| a.parse(&opts.start, &content))
+    {
+        Ok(p) => p.tree(),
+        Err(s) => {
+            eprintln!("{}", s);
+            return 2;
+        }
+    };
+    let interesting = |s: &str| Ok(target.run(s.as_bytes())?.success());
+    match interesting(&tree.text()) {
+        Ok(true) => (),
+        Ok(false) => {
+            eprintln!("{}: the test command rejects the input as it is", path);
+            return 2;
+        }
+        Err(s) => {
+            eprintln!("{}", s);
+            return 2;
+        }
+    }
+    match a.reduce(&tree, interesting) {
+        Ok(small) => {
+            eprintln!("{} -> {} bytes", tree.text().len(), small.text().len());
+            print!("{}", small.text());
+            0
+        }
+        Err(s) => {
+            eprintln!("{}", s);
+            2
+        }
+    }
+}
+
+fn negative(a: &collection::Collection, opts: &Options) {
+    let sampler = match a.negative_sampler(&opts.start) {
+        Ok(n) => n,
+        Err(s) => return println!("{}", s),
+    };
+    let seed = opts.seed.unwrap_or_else(rand::random);
+    eprintln!("seed {}", seed);
+    for i in 0..opts.count.unwrap_or(1) {
+        let mut rng = StdRng::seed_from_u64(collection::sample_seed(seed, i as u64));
+        match sampler.sample(&opts.errors, &mut rng) {
+            Ok(n) => println!("{} [{}]: {}", opts.start, n.label, n.text),
+            Err(s) => println!("{}", s),
+        }
+    }
+}
+
+// run both commands on every sample of a batch and save the inputs whose results differ,
+// with their derivation trees and seeds; returns 0 when they always agree, 1 when not, 2 on errors
+fn diff(a: &collection::Collection, opts: &Options) -> i32 {
+    let timeout = Some(opts.timeout.unwrap_or(Duration::from_secs(1)));
+    let (mut left, mut right) = match (&opts.left, &opts.right) {
+        (Some(l), Some(r)) => (Target::shell(l), Target::shell(r)),
+        _ => {
+            eprintln!("diff expects --left <command> and --right <command>");
+            return 2;
+        }
+    };
+    (left.timeout, right.timeout) = (timeout, timeout);
+    if let Err(e) = fs::create_dir_all(&opts.out) {
+        eprintln!("{}: {}", opts.out, e);
+        return 2;
+    }
+    let seed = opts.seed.unwrap_or_else(rand::random);
+    eprintln!("seed {}", seed);
+    let count = opts.count.unwrap_or(1);
+    let mut divergent = 0;
+    for (i, tree) in a
+        .gen_tree_batch(&opts.start, seed, count, opts.threads)
+        .into_iter()
+        .enumerate()
+    {
+        let result = tree.and_then(|tree| {
+            let input = tree.text();
+            let (l, r) = thread::scope(|s| {
+                let l = s.spawn(|| left.run(&encode(opts, &input)));
+                let r = right.run(&encode(opts, &input));
+                (l.join().unwrap(), r)
             });
+            Ok((tree, input, l?, r?))
+        });
+        let (tree, input, l, r) = match result {
+            Ok(x) => x,
+            Err(s) => {
+                eprintln!("{}", s);
+                return 2;
+            }
+        };
+        let difference = match differential::compare(&l, &r, &opts.normalize) {
+            Some(d) => d,
+            None => continue,
+        };
+        let path = Path::new(&opts.out).join(format!("diff-{:04}", divergent));
+        divergent += 1;
+        eprintln!("{}: sample {}, {}", path.display(), i, difference);
+        let note = format!("seed {}\n", collection::sample_seed(seed, i as u64));
+        let run = |o: &datarobot::exec::Outcome| {
+            let mut v = format!("{}\n", o.status()).into_bytes();
+            v.extend(&o.stdout);
+            v
+        };
+        let files = [
+            ("", encode(opts, &input)),
+            ("seed", note.into_bytes()),
+            ("tree", tree.levels().into_bytes()),
+            ("left", run(&l)),
+            ("right", run(&r)),
+        ];
+        for (ext, data) in files {
+            let p = path.with_extension(ext);
+            if let Err(e) = fs::write(&p, data) {
+                eprintln!("{}: {}", p.display(), e);
+                return 2;
+            }
+        }
     }
+    eprintln!("{} samples, {} divergent", count, divergent);
+    (divergent > 0) as i32
+}
+
+// run the command on generated samples until count runs, saving one input per distinct failure,
+// returns 0 when nothing was found, 1 with findings, 2 on errors
+fn fuzz(a: &collection::Collection, opts: &Options) -> i32 {
+    let mut target = match Target::new(opts.command.clone()) {
+        Ok(t) => t,
+        Err(s) => {
+            eprintln!("{}, give it after --", s);
+            return 2;
+        }
+    };
+    target.timeout = Some(opts.timeout.unwrap_or(Duration::from_secs(1)));
+    let mut findings = match fuzz::Findings::new(&opts.out) {
+        Ok(f) => f,
+        Err(s) => {
+            eprintln!("{}", s);
+            return 2;
+        }
+    };
+    let master = opts.seed.unwrap_or_else(rand::random);
+    eprintln!("seed {}", master);
+    let mut runs = 0;
+    while opts.count.is_none_or(|n| runs < n) {
+        // one round of samples run side by side, handled in index order
+        let round = opts
+            .threads
+            .max(1)
+            .min(opts.count.map_or(usize::MAX, |n| n - runs));
+        let results: Vec<_> = thread::scope(|s| {
+            let handles: Vec<_> = (runs..runs + round)
+                .map(|i| {
+                    let target = &target;
+                    s.spawn(move || {
+                        let seed = collection::sample_seed(master, i as u64);
+                        let mut rng = StdRng::seed_from_u64(seed);
+                        let (input, choices) = a.gen_recorded(&opts.start, &mut rng)?;
+                        let outcome = target.run(&encode(opts, &input))?;
+                        let note = format!("seed {}\nchoices {}", seed, choices.to_base64());
+                        Ok::<_, String>((note, input, outcome))
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        runs += round;
+        for r in results {
+            let saved = r.and_then(|(note, input, outcome)| match fuzz::classify(&outcome) {
+                Some(kind) => Ok(findings
+                    .add(kind, &outcome, &encode(opts, &input), &note)?
+                    .map(|p| (p, outcome.status()))),
+                None => Ok(None),
+            });
+            match saved {
+                Ok(Some((path, status))) => eprintln!("{}: {}", path.display(), status),
+                Ok(None) => (),
+                Err(s) => {
+                    eprintln!("{}", s);
+                    return 2;
+                }
+            }
+        }
+    }
+    eprintln!(
+        "{} runs, {} failing, {} distinct saved to {}",
+        runs,
+        findings.hits(),
+        findings.saved,
+        opts.out
+    );
+    (findings.saved > 0) as i32
+}
+
+// a file, or stdin for "-"; binary input has one char per byte
+fn read_input(path: &str, binary: bool) -> io::Result<String> {
+    let mut data = vec![];
+    match path {
+        "-" => io::Read::read_to_end(&mut io::stdin(), &mut data).map(|_| ())?,
+        _ => data = fs::read(path)?,
+    }
+    match binary {
+        true => Ok(codec::from_bytes(&data)),
+        false => String::from_utf8(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+    }
+}
+
+// the derivations numbered from..to among those of length at most bound, or their count
+fn enumerate(
+    a: &collection::Collection,
+    bnf_expr: &str,
+    bound: usize,
+    range: &Option<(BigUint, BigUint)>,
+) {
+    let counts = match a.counts(bnf_expr, collection::SizeMetric::Text, bound) {
+        Ok(c) => c,
+        Err(s) => return println!("{}", s),
+    };
+    let (from, to) = match range {
+        Some(r) => r,
+        None => return println!("{}: {}", bnf_expr, counts.count_upto(bnf_expr, bound)),
+    };
+    let mut k = from.clone();
+    while k < *to {
+        match counts.unrank(bnf_expr, bound, &k) {
+            Ok(d) => println!("{} {}: {}", bnf_expr, k, d.text()),
+            Err(s) => return println!("{}", s),
+        }
+        k += &BigUint::one();
+    }
+}
+
+fn ambiguity(a: &collection::Collection, bnf_expr: &str, bound: usize) {
+    let r = match a.ambiguity(bnf_expr, bound) {
+        Ok(r) => r,
+        Err(s) => return println!("{}", s),
+    };
+    match r.witness {
+        Some(w) => {
+            println!(
+                "{} is ambiguous: {:?} has two derivations",
+                bnf_expr, w.text
+            );
+            w.trees.0.display();
+            println!();
+            w.trees.1.display();
+        }
+        None => println!(
+            "{}: no ambiguity among {} derivations of length at most {}",
+            bnf_expr, r.checked, r.bound
+        ),
+    }
+}
+
+// expected text length with every alternative equally likely, and with the probabilities gen uses
+fn analyze(a: &collection::Collection, bnf_expr: &str) {
+    let weights = match a.rules() {
+        Ok(rules) => collection::uniform_weights(&rules),
+        Err(s) => return println!("{}", s),
+    };
+    let report = |name: &str, r: Result<collection::SizeReport, String>| match r {
+        Ok(r) => {
+            let expected = match r.expected.get(bnf_expr) {
+                Some(e) => e,
+                None => return println!("No production rule for {}", bnf_expr),
+            };
+            let mut sizes: Vec<_> = r.expected.iter().collect();
+            sizes.sort_by(|x, y| x.0.cmp(y.0));
+            println!("[{}] expected length of {}: {}", name, bnf_expr, expected);
+            for (k, e) in sizes {
+                println!("  {}: {}", k, e);
+            }
+            for d in r.divergent {
+                println!("[{}] warning: {}", name, d);
+            }
+        }
+        Err(s) => println!("{}", s),
+    };
+    report(
+        "uniform",
+        a.size_report(collection::SizeMetric::Text, &weights),
+    );
+    report("gen", a.gen_size_report(bnf_expr));
 }
 
 // The output is wrapped in a Result to allow matching on errors
@@ -4,6 +4,12 @@ this file is a hand crafted minimum BNF parser
 mod display;
 pub mod gen;
 
+use crate::action::Action;
+use crate::builtin::Builtin;
+use crate::computed::Computed;
+use crate::regex::Regex;
+use crate::transform::Transform;
+
 pub enum AstNodeType {
     Bnf,
     Term,
@@ -35,10 +41,49 @@ pub enum Expr {
     },
 }
 
-//<expr0>::="<"<name>">"|"\""<name>"\""
+//<expr0>::="<"<name>">"|"\""<name>"\""|"/"<regex>"/"|"<@"<builtin>">"|"$"<var>|"0x"<hh>|"x\""<hh..>"\""
+//        |"@"<transformer>"("<expr>")"
+// and "<"<name>" as "<var>">" or "<@"<builtin>" as "<var>">" to bind, "0x"<hh>"-0x"<hh> for
+// a byte range, the regex /[\xhh-\xhh]/
 pub enum Expr0 {
-    NonTerminal { term: Box<Ast> },
-    Terminal { name: Box<Ast> },
+    NonTerminal {
+        term: Box<Ast>,
+    },
+    Terminal {
+        name: Box<Ast>,
+    },
+    Regex {
+        regex: Regex,
+    },
+    Builtin {
+        builtin: Builtin,
+    },
+    Computed {
+        field: Computed,
+    },
+    Bytes {
+        bytes: Vec<u8>,
+    }, // 0x89 or x"89504e47", one char U+0000..U+00FF per byte
+    Transform {
+        transform: Transform,
+        expr: Box<Ast>,
+    }, // @name(<expr>)
+    Bind {
+        expr0: Box<Ast>,
+        var: String,
+    },
+    Ref {
+        var: String,
+    },
+}
+
+fn is_var(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// splits "inner as var" into inner and var
+fn split_bind(s: &str) -> Option<(&str, &str)> {
+    s.rsplit_once(" as ").filter(|(_, var)| is_var(var))
 }
 
 //<remain_expr>::=E|<expr>
@@ -63,6 +108,7 @@ pub enum Ast {
     },
     Stmt {
         expr: Box<Ast>,
+        action: Option<Action>, // "{"<action>"}" after the expr
         remain_stmt: Box<Ast>,
         parallels: i32,
     },
@@ -74,6 +120,13 @@ pub enum Ast {
     Epsilon,
 }
 
+// one element of an alternative, flattened out of the Expr/Expr0 chain
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Symbol {
+    Terminal(String),
+    NonTerminal(String), // "<name>", same key as Collection uses
+}
+
 pub fn parse(b: &str) -> Result<Ast, String> {
     Ok(parse_bnf(b, AstNodeType::Bnf)?.r)
 }
@@ -132,14 +185,34 @@ pub fn parse_bnf<'a>(bnfstr: &'a str, state: AstNodeType) -> Result<ParseResult<
             })
         }
         AstNodeType::Stmt => {
-            // <stmt>::=<expr><remain_stmt>
+            // <stmt>::=<expr><remain_stmt>|<expr>"{"<action>"}"<remain_stmt>
             let e = parse_bnf(bnfstr, AstNodeType::Expr)?;
-            let r = parse_bnf(e.remain, AstNodeType::RemainStmt)?;
+            let (action, a_len) = match e.remain.strip_prefix('{') {
+                Some(rest) => {
+                    // the action ends at the first } outside quotes
+                    let mut quote = None;
+                    let end = rest
+                        .find(|c| {
+                            match (c, quote) {
+                                ('\'' | '"', None) => quote = Some(c),
+                                (c, Some(q)) if c == q => quote = None,
+                                ('}', None) => return true,
+                                _ => (),
+                            }
+                            false
+                        })
+                        .ok_or_else(|| format!("[stmt] unclosed action {}", e.remain))?;
+                    (Some(Action::new(&rest[..end])?), end + 2)
+                }
+                None => (None, 0),
+            };
+            let r = parse_bnf(&e.remain[a_len..], AstNodeType::RemainStmt)?;
             Ok(ParseResult {
-                matched: &bnfstr[..e.len() + r.len()],
-                remain: &bnfstr[e.len() + r.len()..],
+                matched: &bnfstr[..e.len() + a_len + r.len()],
+                remain: &bnfstr[e.len() + a_len + r.len()..],
                 r: Ast::Stmt {
                     expr: Box::new(e.r),
+                    action,
                     parallels: match r.r {
                         Ast::RemainStmt(RemainStmt::Epsilon) => 1,
                         Ast::RemainStmt(RemainStmt::OrStmt { stmt: ref s }) => {
@@ -179,18 +252,14 @@ pub fn parse_bnf<'a>(bnfstr: &'a str, state: AstNodeType) -> Result<ParseResult<
                         remain: bnfstr,
                     })
                 }
-                _ => Err(format!(
-                    "[<remain_stmt>::=E|\"|\"<stmt>] expect | or end of line, found {}",
-                    bnfstr
-                )),
             }
         }
         AstNodeType::Expr => {
             // <expr>::="E"|<expr0><remain_expr>
             match bnfstr.len() {
                 1.. => {
-                    // try <expr0><remain_expr>, FIRST(<expr0>) = <"
-                    if &bnfstr[..1] == "<" || &bnfstr[..1] == "\"" {
+                    // try <expr0><remain_expr>, FIRST(<expr0>) = <"/$0x@
+                    if "<\"/$0x@".contains(&bnfstr[..1]) {
                         let e0 = parse_bnf(bnfstr, AstNodeType::Expr0)?;
                         let r = parse_bnf(e0.remain, AstNodeType::RemainExpr)?;
                         Ok(ParseResult {
@@ -211,7 +280,7 @@ pub fn parse_bnf<'a>(bnfstr: &'a str, state: AstNodeType) -> Result<ParseResult<
                         })
                     } else {
                         Err(format!(
-                            "[<expr>::=\"E\"|<expr0><remain_expr>] expect E<\", found {}",
+                            "[<expr>::=\"E\"|<expr0><remain_expr>] expect E<\"/$0x@, found {}",
                             bnfstr
                         ))
                     }
@@ -220,18 +289,72 @@ pub fn parse_bnf<'a>(bnfstr: &'a str, state: AstNodeType) -> Result<ParseResult<
             }
         }
         AstNodeType::Expr0 => {
-            // <expr0>::=<term>|"\""<name>"\""
+            // <expr0>::=<term>|"\""<name>"\""|"/"<regex>"/"|"<@"<builtin>">"
             match bnfstr.len() {
                 1.. => {
-                    if &bnfstr[..1] == "<" {
+                    if let Some(rest) = bnfstr.strip_prefix("<@") {
+                        // try "<@"<builtin>">", arguments may nest <> and () and quote
+                        let (mut depth, mut quoted) = (0, false);
+                        let end = rest
+                            .find(|c| {
+                                match c {
+                                    '"' => quoted = !quoted,
+                                    '(' | '<' if !quoted => depth += 1,
+                                    ')' if !quoted => depth -= 1,
+                                    '>' if !quoted && depth == 0 => return true,
+                                    '>' if !quoted => depth -= 1,
+                                    _ => (),
+                                }
+                                false
+                            })
+                            .ok_or_else(|| format!("[expr0] unclosed built-in {}", bnfstr))?;
+                        let (source, var) = match split_bind(&rest[..end]) {
+                            Some((source, var)) => (source, Some(var)),
+                            None => (&rest[..end], None),
+                        };
+                        let mut r = match Computed::is_computed(source) {
+                            true => Expr0::Computed {
+                                field: Computed::new(source)?,
+                            },
+                            false => Expr0::Builtin {
+                                builtin: Builtin::new(source)?,
+                            },
+                        };
+                        if let Some(var) = var {
+                            r = Expr0::Bind {
+                                expr0: Box::new(Ast::Expr0(r)),
+                                var: var.to_string(),
+                            };
+                        }
+                        Ok(ParseResult {
+                            matched: &bnfstr[..end + 3],
+                            remain: &bnfstr[end + 3..],
+                            r: Ast::Expr0(r),
+                        })
+                    } else if &bnfstr[..1] == "<" {
                         // try <term>
                         let t = parse_bnf(bnfstr, AstNodeType::Term)?;
-                        Ok(ParseResult {
-                            matched: &bnfstr[..t.len()],
-                            remain: &bnfstr[t.len()..],
-                            r: Ast::Expr0(Expr0::NonTerminal {
+                        let len = t.len();
+                        let name = &t.matched[1..len - 1];
+                        let r = match split_bind(name) {
+                            // <name as var> binds the text <name> derives
+                            Some((inner, var)) => {
+                                let inner = format!("<{}>", inner);
+                                Expr0::Bind {
+                                    expr0: Box::new(Ast::Expr0(Expr0::NonTerminal {
+                                        term: Box::new(parse_bnf(&inner, AstNodeType::Term)?.r),
+                                    })),
+                                    var: var.to_string(),
+                                }
+                            }
+                            None => Expr0::NonTerminal {
                                 term: Box::new(t.r),
-                            }),
+                            },
+                        };
+                        Ok(ParseResult {
+                            matched: &bnfstr[..len],
+                            remain: &bnfstr[len..],
+                            r: Ast::Expr0(r),
                         })
                     } else if &bnfstr[..1] == "\"" {
                         // try "\""<name>"\""
@@ -245,9 +368,106 @@ pub fn parse_bnf<'a>(bnfstr: &'a str, state: AstNodeType) -> Result<ParseResult<
                                 name: Box::new(n.r),
                             }),
                         })
+                    } else if &bnfstr[..1] == "/" {
+                        // try "/"<regex>"/", the regex ends at the first unescaped /
+                        let mut escaped = false;
+                        let end = bnfstr[1..]
+                            .find(|c| {
+                                let close = c == '/' && !escaped;
+                                escaped = c == '\\' && !escaped;
+                                close
+                            })
+                            .ok_or_else(|| format!("[expr0] unclosed regex {}", bnfstr))?;
+                        let regex = Regex::new(&bnfstr[1..end + 1])?;
+                        Ok(ParseResult {
+                            matched: &bnfstr[..end + 2],
+                            remain: &bnfstr[end + 2..],
+                            r: Ast::Expr0(Expr0::Regex { regex }),
+                        })
+                    } else if let Some(rest) = bnfstr.strip_prefix("0x") {
+                        // try "0x"<hh>, or the byte range "0x"<hh>"-0x"<hh>
+                        let byte = |s: &str| {
+                            s.get(..2)
+                                .filter(|h| h.chars().all(|c| c.is_ascii_hexdigit()))
+                                .map(|h| u8::from_str_radix(h, 16).unwrap())
+                        };
+                        let lo = byte(rest).ok_or_else(|| {
+                            format!("[expr0] expect two hex digits in {}", bnfstr)
+                        })?;
+                        let (r, len) = match rest[2..].strip_prefix("-0x").and_then(byte) {
+                            Some(hi) if hi < lo => {
+                                return Err(format!("[expr0] bad byte range in {}", bnfstr))
+                            }
+                            Some(hi) => {
+                                let pattern = format!("[\\x{:02x}-\\x{:02x}]", lo, hi);
+                                let regex = Regex::new(&pattern)?;
+                                (Expr0::Regex { regex }, 9)
+                            }
+                            None => (Expr0::Bytes { bytes: vec![lo] }, 4),
+                        };
+                        Ok(ParseResult {
+                            matched: &bnfstr[..len],
+                            remain: &bnfstr[len..],
+                            r: Ast::Expr0(r),
+                        })
+                    } else if let Some(rest) = bnfstr.strip_prefix("x\"") {
+                        // try "x\""<hh..>"\""
+                        let hex = &rest[..rest
+                            .find('"')
+                            .ok_or_else(|| format!("[expr0] unclosed bytes {}", bnfstr))?];
+                        if hex.is_empty()
+                            || hex.len() % 2 == 1
+                            || !hex.chars().all(|c| c.is_ascii_hexdigit())
+                        {
+                            return Err(format!(
+                                "[expr0] expect pairs of hex digits in {}",
+                                bnfstr
+                            ));
+                        }
+                        let bytes = (0..hex.len())
+                            .step_by(2)
+                            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+                            .collect();
+                        Ok(ParseResult {
+                            matched: &bnfstr[..hex.len() + 3],
+                            remain: &bnfstr[hex.len() + 3..],
+                            r: Ast::Expr0(Expr0::Bytes { bytes }),
+                        })
+                    } else if let Some(rest) = bnfstr.strip_prefix('@') {
+                        // try "@"<transformer>"("<expr>")"
+                        let (name, inner) = rest
+                            .split_once('(')
+                            .ok_or_else(|| format!("[expr0] expect ( after @ in {}", bnfstr))?;
+                        let transform = Transform::new(name)?;
+                        let e = parse_bnf(inner, AstNodeType::Expr)?;
+                        let close = match_chars("expr0", e.remain, ")")?;
+                        let len = name.len() + e.len() + 3;
+                        Ok(ParseResult {
+                            matched: &bnfstr[..len],
+                            remain: close.remain,
+                            r: Ast::Expr0(Expr0::Transform {
+                                transform,
+                                expr: Box::new(e.r),
+                            }),
+                        })
+                    } else if let Some(rest) = bnfstr.strip_prefix('$') {
+                        // try "$"<var>
+                        let end = rest
+                            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                            .unwrap_or(rest.len());
+                        if end == 0 {
+                            return Err(format!("[expr0] expect a variable after $ in {}", bnfstr));
+                        }
+                        Ok(ParseResult {
+                            matched: &bnfstr[..end + 1],
+                            remain: &bnfstr[end + 1..],
+                            r: Ast::Expr0(Expr0::Ref {
+                                var: rest[..end].to_string(),
+                            }),
+                        })
                     } else {
                         Err(format!(
-                            r#"[<expr0>::="<"<name>">"|"""<name>"""] expect <\", found {}"#,
+                            r#"[<expr0>::="<"<name>">"|"""<name>"""|"/"<regex>"/"|"$"<var>|"0x"<hh>|"x""<hh..>""|"@"<transformer>"("<expr>")"] expect <\"/$0x@, found {}"#,
                             bnfstr
                         ))
                     }
@@ -259,8 +479,8 @@ pub fn parse_bnf<'a>(bnfstr: &'a str, state: AstNodeType) -> Result<ParseResult<
             // <remain_expr>::=E|<expr>
             match bnfstr.len() {
                 1.. => {
-                    if "E<\"".chars().any(|x| x.to_string() == bnfstr[..1]) {
-                        // try <expr>, FIRST(expr) = E<"
+                    if "E<\"/$0x@".chars().any(|x| x.to_string() == bnfstr[..1]) {
+                        // try <expr>, FIRST(expr) = E<"/$0x@
                         let e = parse_bnf(bnfstr, AstNodeType::Expr)?;
                         Ok(ParseResult {
                             matched: e.matched,
@@ -269,8 +489,8 @@ pub fn parse_bnf<'a>(bnfstr: &'a str, state: AstNodeType) -> Result<ParseResult<
                                 expr: Box::new(e.r),
                             }),
                         })
-                    } else if "|" == &bnfstr[..1] {
-                        // try E, FOLLOW(remain expr) = |$, $ for endmark
+                    } else if "|{)".contains(&bnfstr[..1]) {
+                        // try E, FOLLOW(remain expr) = |{)$, $ for endmark
                         Ok(ParseResult {
                             r: Ast::RemainExpr(RemainExpr::Epsilon),
                             matched: "",
@@ -278,7 +498,7 @@ pub fn parse_bnf<'a>(bnfstr: &'a str, state: AstNodeType) -> Result<ParseResult<
                         })
                     } else {
                         Err(format!(
-                            "[<remain_expr>::=E|<expr>] expect E<\"|, found {}\n",
+                            "[<remain_expr>::=E|<expr>] expect E<\"/$0x@|{{), found {}\n",
                             bnfstr
                         ))
                     }
@@ -289,7 +509,6 @@ pub fn parse_bnf<'a>(bnfstr: &'a str, state: AstNodeType) -> Result<ParseResult<
                     matched: "",
                     remain: bnfstr,
                 }),
-                _ => Err("no match rule".to_string()),
             }
         }
         AstNodeType::Name => {
@@ -336,7 +555,6 @@ pub fn parse_bnf<'a>(bnfstr: &'a str, state: AstNodeType) -> Result<ParseResult<
                         remain: bnfstr,
                     })
                 }
-                _ => Err(format!("no match rule for {}", bnfstr)),
             }
         }
     }
@@ -385,6 +603,29 @@ mod tests {
             parse_bnf(r#""aaa""#, AstNodeType::Expr0).unwrap().matched,
             r#""aaa""#
         );
+        let r = parse_bnf(r"/[A-Z]{2}\/[0-9]{6}/<a>", AstNodeType::Expr0).unwrap();
+        assert_eq!((r.matched, r.remain), (r"/[A-Z]{2}\/[0-9]{6}/", "<a>"));
+        assert_eq!(r.r.bnf(), r"/[A-Z]{2}\/[0-9]{6}/");
+        assert!(parse_bnf("/[A-Z/", AstNodeType::Expr0).is_err());
+        assert!(parse_bnf("/[A-Z]", AstNodeType::Expr0).is_err());
+        let r = parse_bnf(
+            r#"<@date(2020-01-01,2025-12-31,%Y-%m-%d)>"x""#,
+            AstNodeType::Expr0,
+        )
+        .unwrap();
+        assert_eq!(r.remain, r#""x""#);
+        assert_eq!(r.r.bnf(), "<@date(2020-01-01,2025-12-31,%Y-%m-%d)>");
+        assert!(parse_bnf("<@int(1,2>", AstNodeType::Expr0).is_err());
+        assert!(parse_bnf("<@nope>", AstNodeType::Expr0).is_err());
+    }
+
+    #[test]
+    fn bindings() {
+        for e0 in ["<tag as t>", "<@int(1,9) as n>", "$t"] {
+            assert_eq!(parse_bnf(e0, AstNodeType::Expr0).unwrap().r.bnf(), e0);
+        }
+        let r = parse_bnf("$n_2<a>", AstNodeType::Expr0).unwrap();
+        assert_eq!(r.remain, "<a>");
     }
 
     #[test]
@@ -41,6 +41,38 @@ impl Ast {
                 ret.append(&mut t.mk_str_vec());
                 ret
             }
+            Ast::Expr0(Expr0::Regex { regex }) => {
+                vec![
+                    vec![(0, "Expr".to_string())],
+                    vec![(0, format!("/{}/", regex.pattern))],
+                ]
+            }
+            Ast::Expr0(Expr0::Builtin { builtin }) => {
+                vec![vec![(0, "Expr".to_string())], vec![(0, builtin.key())]]
+            }
+            Ast::Expr0(Expr0::Computed { field }) => {
+                vec![vec![(0, "Expr".to_string())], vec![(0, field.key())]]
+            }
+            Ast::Expr0(Expr0::Transform { transform, expr }) => {
+                let mut ret = vec![vec![(0, "Expr".to_string())]];
+                ret.append(&mut expr.mk_str_vec());
+                ret[1].push((1, format!("@{}", transform.name)));
+                ret
+            }
+            Ast::Expr0(Expr0::Bytes { .. }) => {
+                vec![vec![(0, "Expr".to_string())], vec![(0, self.bnf())]]
+            }
+            Ast::Expr0(Expr0::Bind { expr0, var }) => {
+                let mut ret = expr0.mk_str_vec();
+                ret[0].push((1, format!("as {}", var)));
+                ret
+            }
+            Ast::Expr0(Expr0::Ref { var }) => {
+                vec![
+                    vec![(0, "Expr".to_string())],
+                    vec![(0, format!("${}", var))],
+                ]
+            }
             Ast::Name(Name::Epsilon) => {
                 vec![vec![(0, "Name".to_string())], vec![(0, "e".to_string())]]
             }
@@ -101,7 +133,11 @@ impl Ast {
             print!("#{}  ", level + 1);
             for (nu, word) in line {
                 let n_tree = *nu as u32 + 2_u32.pow(level as u32) - 1;
-                let pa_n_tree = if n_tree == 0 { 0 } else { (1 + n_tree) / 2 - 1 };
+                let pa_n_tree = if n_tree == 0 {
+                    0
+                } else {
+                    n_tree.div_ceil(2) - 1
+                };
                 let pa_nu = if pa_n_tree == 0 {
                     0
                 } else {
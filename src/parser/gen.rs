@@ -1,5 +1,18 @@
 use super::*;
-use rand::Rng;
+use crate::action::Slot;
+use crate::codec::from_bytes;
+use crate::computed::{Computed, Target};
+use crate::transform::Transform;
+use std::collections::{HashMap, HashSet};
+
+// computed fields of an alternative as (position, field, position of its target)
+pub type Fields<'a> = Vec<(usize, &'a Computed, usize)>;
+
+// the nonterminal of a transformer, its rule is the shape of the output; @@ is
+// no built-in
+pub fn transform_key(transform: &Transform, expr: &Ast) -> String {
+    format!("<@@{}({})>", transform.name, expr.bnf().trim_end())
+}
 
 impl Ast {
     pub fn bnf(&self) -> String {
@@ -16,6 +29,27 @@ impl Ast {
             }
             Ast::Expr0(Expr0::Terminal { name: n }) => format!("\"{}\"", n.bnf()),
             Ast::Expr0(Expr0::NonTerminal { term: t }) => t.bnf(),
+            Ast::Expr0(Expr0::Regex { regex }) => format!("/{}/", regex.pattern),
+            Ast::Expr0(Expr0::Builtin { builtin }) => builtin.key(),
+            Ast::Expr0(Expr0::Computed { field }) => field.key(),
+            Ast::Expr0(Expr0::Transform { transform, expr }) => {
+                format!("@{}({})", transform.name, expr.bnf().trim_end())
+            }
+            Ast::Expr0(Expr0::Bytes { bytes }) => match bytes[..] {
+                [b] => format!("0x{:02x}", b),
+                _ => format!(
+                    "x\"{}\"",
+                    bytes
+                        .iter()
+                        .map(|b| format!("{:02x}", b))
+                        .collect::<String>()
+                ),
+            },
+            Ast::Expr0(Expr0::Bind { expr0, var }) => {
+                let inner = expr0.bnf();
+                format!("{} as {}>", inner.trim_end_matches('>'), var)
+            }
+            Ast::Expr0(Expr0::Ref { var }) => format!("${}", var),
             Ast::Name(Name::Epsilon) => "".to_string(),
             Ast::Name(Name::HeadTail { head: h, tail: t }) => {
                 format!("{}{}", h, t.bnf())
@@ -28,15 +62,229 @@ impl Ast {
             }
             Ast::Stmt {
                 expr: e,
+                action,
                 remain_stmt: r,
                 ..
-            } => {
-                format!("{}{}", e.bnf(), r.bnf())
-            }
+            } => match action {
+                Some(a) => format!("{}{{{}}} {}", e.bnf(), a.source, r.bnf()),
+                None => format!("{}{}", e.bnf(), r.bnf()),
+            },
             Ast::Term { name: n } => {
                 format!("<{}>", n.bnf())
             }
             Ast::Epsilon => "".to_string(),
         }
     }
+
+    // alternatives of a Bnf or Stmt, each as a sequence of symbols
+    pub fn alternatives(&self) -> Vec<Vec<Symbol>> {
+        self.exprs().into_iter().map(|(e, _)| e.symbols()).collect()
+    }
+
+    // the action of every alternative of a Bnf or Stmt, with the positions of its
+    // bound variables
+    pub fn actions(&self) -> Vec<(Option<&Action>, HashMap<&str, usize>)> {
+        self.exprs()
+            .into_iter()
+            .map(|(e, action)| (action, e.bound()))
+            .collect()
+    }
+
+    // the Expr and action of every alternative of a Bnf or Stmt
+    fn exprs(&self) -> Vec<(&Ast, Option<&Action>)> {
+        let mut exprs = vec![];
+        let mut cur = self;
+        loop {
+            match cur {
+                Ast::Bnf(b) => cur = &b.stmt,
+                Ast::Stmt {
+                    expr: e,
+                    action,
+                    remain_stmt: r,
+                    ..
+                } => {
+                    exprs.push((&**e, action.as_ref()));
+                    cur = r;
+                }
+                Ast::RemainStmt(RemainStmt::OrStmt { stmt: s }) => cur = s,
+                _ => return exprs,
+            }
+        }
+    }
+
+    // the Expr0s of an Expr, in order
+    fn chain(&self) -> Vec<&Expr0> {
+        let mut chain = vec![];
+        let mut cur = self;
+        loop {
+            match cur {
+                Ast::Expr(Expr::Expr0Remain {
+                    expr0: e0,
+                    remain_expr: r,
+                }) => {
+                    if let Ast::Expr0(e0) = &**e0 {
+                        chain.push(e0);
+                    }
+                    cur = r;
+                }
+                Ast::RemainExpr(RemainExpr::Expr { expr: e }) => cur = e,
+                _ => return chain,
+            }
+        }
+    }
+
+    // symbols of an Expr, in order; a $reference stands for the symbol it is bound
+    // to, which derives its value among others
+    pub fn symbols(&self) -> Vec<Symbol> {
+        fn symbol(e0: &Expr0) -> Symbol {
+            match e0 {
+                Expr0::Terminal { name: n } => Symbol::Terminal(n.bnf()),
+                Expr0::NonTerminal { term: t } => Symbol::NonTerminal(t.bnf()),
+                Expr0::Regex { regex } => Symbol::NonTerminal(regex.key()),
+                Expr0::Builtin { builtin } => Symbol::NonTerminal(builtin.key()),
+                Expr0::Computed { field } => Symbol::NonTerminal(field.key()),
+                Expr0::Bytes { bytes } => Symbol::Terminal(from_bytes(bytes)),
+                Expr0::Transform { transform, expr } => {
+                    Symbol::NonTerminal(transform_key(transform, expr))
+                }
+                Expr0::Bind { expr0, .. } => match &**expr0 {
+                    Ast::Expr0(e0) => symbol(e0),
+                    _ => Symbol::Terminal("".to_string()),
+                },
+                Expr0::Ref { .. } => Symbol::Terminal("".to_string()),
+            }
+        }
+        let mut bound = HashMap::new();
+        self.chain()
+            .into_iter()
+            .map(|e0| {
+                let sym = match e0 {
+                    Expr0::Ref { var } => bound.get(var).cloned(),
+                    _ => None,
+                };
+                let sym = sym.unwrap_or_else(|| symbol(e0));
+                if let Expr0::Bind { var, .. } = e0 {
+                    bound.insert(var.clone(), sym.clone());
+                }
+                sym
+            })
+            .collect()
+    }
+
+    // the computed fields of every alternative of a Bnf or Stmt
+    pub fn alternative_fields(&self) -> Result<Vec<Fields<'_>>, String> {
+        self.exprs().into_iter().map(|(e, _)| e.fields()).collect()
+    }
+
+    // the computed fields of an Expr with the position of each and of its target,
+    // the first symbol of the target nonterminal or the one bound to the variable
+    fn fields(&self) -> Result<Fields<'_>, String> {
+        let chain = self.chain();
+        let bound = self.bound();
+        let nonterminal = |e0: &Expr0| match e0 {
+            Expr0::NonTerminal { term } => Some(term.bnf()),
+            Expr0::Bind { expr0, .. } => match &**expr0 {
+                Ast::Expr0(Expr0::NonTerminal { term }) => Some(term.bnf()),
+                _ => None,
+            },
+            _ => None,
+        };
+        let mut fields = vec![];
+        for (i, e0) in chain.iter().enumerate() {
+            if let Expr0::Computed { field } = e0 {
+                let target = match &field.target {
+                    Target::Var(var) => bound.get(var.as_str()).copied(),
+                    Target::Symbol(key) => chain
+                        .iter()
+                        .position(|e0| nonterminal(e0).as_ref() == Some(key)),
+                };
+                let target = target.ok_or_else(|| {
+                    format!("{} refers to no symbol of its alternative", field.key())
+                })?;
+                fields.push((i, field, target));
+            }
+        }
+        Ok(fields)
+    }
+
+    // positions of the variables bound in an Expr
+    fn bound(&self) -> HashMap<&str, usize> {
+        let mut bound = HashMap::new();
+        for (i, e0) in self.chain().into_iter().enumerate() {
+            if let Expr0::Bind { var, .. } = e0 {
+                bound.insert(var.as_str(), i);
+            }
+        }
+        bound
+    }
+
+    // every $reference must follow its binding in the same alternative, an action
+    // may refer to any symbol of its alternative
+    pub fn check_bindings(&self) -> Result<(), String> {
+        for (expr, action) in self.exprs() {
+            let mut bound = HashSet::new();
+            let chain = expr.chain();
+            for e0 in &chain {
+                match e0 {
+                    Expr0::Bind { expr0, .. }
+                        if matches!(**expr0, Ast::Expr0(Expr0::Computed { .. })) =>
+                    {
+                        return Err("a computed field can not be bound".to_string())
+                    }
+                    Expr0::Bind { var, .. } => {
+                        bound.insert(var);
+                    }
+                    Expr0::Ref { var } if !bound.contains(var) => {
+                        return Err(format!("${} is not bound before its use", var))
+                    }
+                    _ => (),
+                }
+            }
+            expr.fields()?;
+            for slot in action.map_or(vec![], |a| a.slots()) {
+                match slot {
+                    Slot::Pos(n) if *n > chain.len() => {
+                        return Err(format!("${} is past the end of its alternative", n))
+                    }
+                    Slot::Name(var) if !bound.contains(var) => {
+                        return Err(format!("${} is not bound in its alternative", var))
+                    }
+                    _ => (),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // the Expr0 nodes of a Bnf, in order
+    pub fn expr0s(&self) -> Vec<&Expr0> {
+        let mut found = vec![];
+        let mut todo = vec![self];
+        while let Some(ast) = todo.pop() {
+            match ast {
+                Ast::Bnf(b) => todo.push(&b.stmt),
+                Ast::Stmt {
+                    expr: e,
+                    remain_stmt: r,
+                    ..
+                } => todo.extend([&**r, &**e]),
+                Ast::RemainStmt(RemainStmt::OrStmt { stmt: s }) => todo.push(s),
+                Ast::Expr(Expr::Expr0Remain {
+                    expr0: e0,
+                    remain_expr: r,
+                }) => todo.extend([&**r, &**e0]),
+                Ast::RemainExpr(RemainExpr::Expr { expr: e }) => todo.push(e),
+                Ast::Expr0(e0) => {
+                    match e0 {
+                        Expr0::Bind { expr0, .. } => todo.push(expr0),
+                        Expr0::Transform { expr, .. } => todo.push(expr),
+                        _ => (),
+                    }
+                    found.push(e0)
+                }
+                _ => (),
+            }
+        }
+        found
+    }
 }
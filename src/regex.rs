@@ -0,0 +1,540 @@
+// regular expressions for regex terminals /.../ in mBNF
+//
+// Supported: literals, escapes (\d \w \s \n \t \r, \xHH for U+0000..U+00FF, that is a
+// byte in binary output, and escaped punctuation), classes
+// [a-z0-9_] and [^...], ".", groups (...) and (?:...), alternation "|", and the
+// quantifiers * + ? {n} {n,} {n,m}. "." and negated classes range over printable
+// ascii. Samples bound open repetitions by MAX_REPEAT; the automaton does not.
+// Every state of the automaton becomes a rule, so a regex needing more than
+// MAX_STATES of them is an error.
+use crate::collection::Chooser;
+use crate::parser::Symbol;
+use std::collections::{BTreeSet, HashMap};
+
+pub const MAX_REPEAT: usize = 8; // extra repetitions sampled for *, + and {n,}
+pub const MAX_STATES: usize = 1000; // states of the deterministic automaton
+const MAX_NFA_STATES: usize = 20 * MAX_STATES;
+
+pub enum Node {
+    Empty,
+    Chars(Vec<char>), // one of, sorted
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+    },
+}
+
+pub struct Regex {
+    pub pattern: String, // as written between the slashes
+    pub node: Node,
+    dfa: Dfa,
+}
+
+fn printable() -> impl Iterator<Item = char> {
+    (0x20u8..=0x7e).map(char::from)
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn alt(&mut self) -> Result<Node, String> {
+        let mut alts = vec![self.concat()?];
+        while self.chars.peek() == Some(&'|') {
+            self.chars.next();
+            alts.push(self.concat()?);
+        }
+        Ok(if alts.len() == 1 {
+            alts.pop().unwrap()
+        } else {
+            Node::Alt(alts)
+        })
+    }
+
+    fn concat(&mut self) -> Result<Node, String> {
+        let mut items = vec![];
+        while let Some(&c) = self.chars.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            items.push(self.quantified(atom)?);
+        }
+        Ok(match items.len() {
+            0 => Node::Empty,
+            1 => items.pop().unwrap(),
+            _ => Node::Concat(items),
+        })
+    }
+
+    fn quantified(&mut self, mut node: Node) -> Result<Node, String> {
+        loop {
+            let (min, max) = match self.chars.peek() {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                Some('{') => {
+                    self.chars.next();
+                    let mut body = String::new();
+                    for c in self.chars.by_ref() {
+                        if c == '}' {
+                            break;
+                        }
+                        body.push(c);
+                    }
+                    let num = |s: &str| {
+                        s.trim()
+                            .parse::<usize>()
+                            .map_err(|_| format!("bad repetition {{{}}}", body))
+                    };
+                    let (min, max) = match body.split_once(',') {
+                        None => (num(&body)?, Some(num(&body)?)),
+                        Some((a, b)) if b.trim().is_empty() => (num(a)?, None),
+                        Some((a, b)) => (num(a)?, Some(num(b)?)),
+                    };
+                    if max.is_some_and(|m| m < min) {
+                        return Err(format!("bad repetition {{{}}}", body));
+                    }
+                    node = Node::Repeat {
+                        node: Box::new(node),
+                        min,
+                        max,
+                    };
+                    continue;
+                }
+                _ => return Ok(node),
+            };
+            self.chars.next();
+            node = Node::Repeat {
+                node: Box::new(node),
+                min,
+                max,
+            };
+        }
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        match self.chars.next() {
+            Some('(') => {
+                if self.chars.peek() == Some(&'?') {
+                    self.chars.next();
+                    if self.chars.next() != Some(':') {
+                        return Err("only (?:...) groups are supported".to_string());
+                    }
+                }
+                let inner = self.alt()?;
+                match self.chars.next() {
+                    Some(')') => Ok(inner),
+                    _ => Err("unclosed group".to_string()),
+                }
+            }
+            Some('[') => self.class(),
+            Some('.') => Ok(Node::Chars(printable().collect())),
+            Some('\\') => Ok(Node::Chars(self.escape()?)),
+            Some(c @ ('*' | '+' | '?' | '{' | '}' | ']' | ')' | '^' | '$')) => {
+                Err(format!("unexpected {} in regex", c))
+            }
+            Some(c) => Ok(Node::Chars(vec![c])),
+            None => Err("regex ends early".to_string()),
+        }
+    }
+
+    fn escape(&mut self) -> Result<Vec<char>, String> {
+        Ok(match self.chars.next() {
+            Some('d') => ('0'..='9').collect(),
+            Some('w') => ('0'..='9')
+                .chain('A'..='Z')
+                .chain(std::iter::once('_'))
+                .chain('a'..='z')
+                .collect(),
+            Some('s') => vec!['\t', '\n', '\r', ' '],
+            Some('n') => vec!['\n'],
+            Some('t') => vec!['\t'],
+            Some('r') => vec!['\r'],
+            Some('x') => {
+                let hex: String = self.chars.by_ref().take(2).collect();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(b) if hex.len() == 2 => vec![char::from(b)],
+                    _ => return Err(format!("bad escape \\x{}", hex)),
+                }
+            }
+            Some(c) if !c.is_alphanumeric() => vec![c],
+            Some(c) => return Err(format!("unknown escape \\{}", c)),
+            None => return Err("regex ends in \\".to_string()),
+        })
+    }
+
+    fn class(&mut self) -> Result<Node, String> {
+        let negated = self.chars.peek() == Some(&'^');
+        if negated {
+            self.chars.next();
+        }
+        let mut set = BTreeSet::new();
+        loop {
+            let c = match self.chars.next() {
+                Some(']') => break,
+                // a class escape like \d starts no range
+                Some('\\') => {
+                    let escaped = self.escape()?;
+                    if escaped.len() != 1 {
+                        set.extend(escaped);
+                        continue;
+                    }
+                    escaped[0]
+                }
+                Some(c) => c,
+                None => return Err("unclosed class".to_string()),
+            };
+            let mut ahead = self.chars.clone();
+            if ahead.next() == Some('-') && ahead.peek().is_some_and(|&e| e != ']') {
+                self.chars.next();
+                let end = match self.chars.next().unwrap() {
+                    '\\' => match self.escape()?[..] {
+                        [end] => end,
+                        _ => return Err(format!("bad range end after {}-", c)),
+                    },
+                    end => end,
+                };
+                if end < c {
+                    return Err(format!("bad range {}-{}", c, end));
+                }
+                set.extend(c..=end);
+            } else {
+                set.insert(c);
+            }
+        }
+        let chars: Vec<char> = match negated {
+            true => printable().filter(|c| !set.contains(c)).collect(),
+            false => set.into_iter().collect(),
+        };
+        match chars.is_empty() {
+            true => Err("class matches nothing".to_string()),
+            false => Ok(Node::Chars(chars)),
+        }
+    }
+}
+
+// pattern matching s literally
+pub fn escape(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        if !c.is_alphanumeric() && c != ' ' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        let mut p = Parser {
+            chars: pattern.chars().peekable(),
+        };
+        let node = p.alt()?;
+        if let Some(c) = p.chars.next() {
+            return Err(format!("unexpected {} in regex /{}/", c, pattern));
+        }
+        let too_large = || {
+            format!(
+                "regex /{}/ needs more than {} automaton states",
+                pattern, MAX_STATES
+            )
+        };
+        let nfa = Nfa::new(&node).ok_or_else(too_large)?;
+        let dfa = Dfa::new(&nfa).ok_or_else(too_large)?;
+        Ok(Regex {
+            pattern: pattern.to_string(),
+            node,
+            dfa,
+        })
+    }
+
+    // a matching string, every decision taken from chooser
+    pub fn sample<C: Chooser + ?Sized>(&self, chooser: &mut C) -> Result<String, String> {
+        fn walk<C: Chooser + ?Sized>(n: &Node, c: &mut C, out: &mut String) -> Result<(), String> {
+            match n {
+                Node::Empty => (),
+                Node::Chars(set) if set.len() == 1 => out.push(set[0]),
+                Node::Chars(set) => out.push(set[c.pick(set.len())?]),
+                Node::Concat(v) => {
+                    for n in v {
+                        walk(n, c, out)?;
+                    }
+                }
+                Node::Alt(v) => walk(&v[c.pick(v.len())?], c, out)?,
+                Node::Repeat { node, min, max } => {
+                    let max = max.unwrap_or(min + MAX_REPEAT);
+                    for _ in 0..c.repeat(*min, max)? {
+                        walk(node, c, out)?;
+                    }
+                }
+            }
+            Ok(())
+        }
+        let mut out = String::new();
+        walk(&self.node, chooser, &mut out)?;
+        Ok(out)
+    }
+
+    // expected length in bytes of sample with every decision uniform, as Sampler takes
+    // them
+    pub fn expected_len(&self) -> f64 {
+        fn len(n: &Node) -> f64 {
+            match n {
+                Node::Empty => 0.0,
+                Node::Chars(set) => {
+                    set.iter().map(|c| c.len_utf8()).sum::<usize>() as f64 / set.len() as f64
+                }
+                Node::Concat(v) => v.iter().map(len).sum(),
+                Node::Alt(v) => v.iter().map(len).sum::<f64>() / v.len() as f64,
+                Node::Repeat { node, min, max } => {
+                    let max = max.unwrap_or(min + MAX_REPEAT);
+                    (min + max) as f64 / 2.0 * len(node)
+                }
+            }
+        }
+        len(&self.node)
+    }
+
+    // the rule name standing for this regex in Collection::rules, "~" never occurs in names
+    pub fn key(&self) -> String {
+        format!("<~/{}/>", self.pattern)
+    }
+
+    // the automaton as a right linear grammar: one rule per dfa state, one
+    // alternative per character, and an empty alternative for accepting states
+    pub fn rules(&self) -> Vec<(String, Vec<Vec<Symbol>>)> {
+        let name = |s: usize| match s {
+            0 => self.key(),
+            _ => format!("<~/{}/#{}>", self.pattern, s),
+        };
+        self.dfa
+            .states
+            .iter()
+            .enumerate()
+            .map(|(s, st)| {
+                let mut alts: Vec<Vec<Symbol>> = st
+                    .next
+                    .iter()
+                    .map(|(c, t)| {
+                        vec![
+                            Symbol::Terminal(c.to_string()),
+                            Symbol::NonTerminal(name(*t)),
+                        ]
+                    })
+                    .collect();
+                if st.accept {
+                    alts.push(vec![]);
+                }
+                (name(s), alts)
+            })
+            .collect()
+    }
+
+    pub fn is_match(&self, s: &str) -> bool {
+        let states = &self.dfa.states;
+        let mut state = 0;
+        for c in s.chars() {
+            match states[state].next.binary_search_by_key(&c, |(d, _)| *d) {
+                Ok(i) => state = states[state].next[i].1,
+                Err(_) => return false,
+            }
+        }
+        states[state].accept
+    }
+}
+
+// thompson automaton, state 0 starts and state 1 accepts
+struct Nfa {
+    edges: Vec<Vec<(Option<Vec<char>>, usize)>>, // None for epsilon
+}
+
+impl Nfa {
+    // None beyond MAX_NFA_STATES, bounded repeats are unrolled
+    fn new(node: &Node) -> Option<Self> {
+        let mut nfa = Nfa {
+            edges: vec![vec![], vec![]],
+        };
+        nfa.build(node, 0, 1)?;
+        Some(nfa)
+    }
+
+    fn state(&mut self) -> Option<usize> {
+        if self.edges.len() >= MAX_NFA_STATES {
+            return None;
+        }
+        self.edges.push(vec![]);
+        Some(self.edges.len() - 1)
+    }
+
+    // edges from a to b that match node
+    fn build(&mut self, node: &Node, a: usize, b: usize) -> Option<()> {
+        match node {
+            Node::Empty => self.edges[a].push((None, b)),
+            Node::Chars(set) => self.edges[a].push((Some(set.clone()), b)),
+            Node::Concat(v) => {
+                let mut from = a;
+                for (i, n) in v.iter().enumerate() {
+                    let to = if i + 1 == v.len() { b } else { self.state()? };
+                    self.build(n, from, to)?;
+                    from = to;
+                }
+            }
+            Node::Alt(v) => {
+                for n in v {
+                    self.build(n, a, b)?;
+                }
+            }
+            Node::Repeat { node, min, max } => {
+                let mut from = a;
+                for _ in 0..*min {
+                    let to = self.state()?;
+                    self.build(node, from, to)?;
+                    from = to;
+                }
+                match max {
+                    Some(max) => {
+                        for _ in *min..*max {
+                            let to = self.state()?;
+                            self.edges[from].push((None, b));
+                            self.build(node, from, to)?;
+                            from = to;
+                        }
+                        self.edges[from].push((None, b));
+                    }
+                    None => {
+                        let lp = self.state()?;
+                        self.edges[from].push((None, lp));
+                        self.build(node, lp, lp)?;
+                        self.edges[lp].push((None, b));
+                    }
+                }
+            }
+        }
+        Some(())
+    }
+
+    fn closure(&self, set: BTreeSet<usize>) -> BTreeSet<usize> {
+        let mut todo: Vec<usize> = set.iter().copied().collect();
+        let mut set = set;
+        while let Some(s) = todo.pop() {
+            for (label, t) in &self.edges[s] {
+                if label.is_none() && set.insert(*t) {
+                    todo.push(*t);
+                }
+            }
+        }
+        set
+    }
+}
+
+struct DfaState {
+    next: Vec<(char, usize)>, // sorted by char
+    accept: bool,
+}
+
+// subset construction, deterministic so every matching string has one derivation
+struct Dfa {
+    states: Vec<DfaState>,
+}
+
+impl Dfa {
+    // None beyond MAX_STATES
+    fn new(nfa: &Nfa) -> Option<Self> {
+        let start = nfa.closure(BTreeSet::from([0]));
+        let mut index = HashMap::from([(start.clone(), 0)]);
+        let mut sets = vec![start];
+        let mut states = vec![];
+        while states.len() < sets.len() {
+            let set = sets[states.len()].clone();
+            let mut moves: HashMap<char, BTreeSet<usize>> = HashMap::new();
+            for &s in &set {
+                for (label, t) in &nfa.edges[s] {
+                    for &c in label.iter().flatten() {
+                        moves.entry(c).or_default().insert(*t);
+                    }
+                }
+            }
+            let mut next: Vec<(char, usize)> = moves
+                .into_iter()
+                .map(|(c, targets)| {
+                    let target = nfa.closure(targets);
+                    let id = *index.entry(target.clone()).or_insert_with(|| {
+                        sets.push(target);
+                        sets.len() - 1
+                    });
+                    (c, id)
+                })
+                .collect();
+            next.sort();
+            states.push(DfaState {
+                next,
+                accept: set.contains(&1),
+            });
+            if sets.len() > MAX_STATES {
+                return None;
+            }
+        }
+        Some(Dfa { states })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn automaton() {
+        let r = Regex::new(r"[A-Z]{2}[0-9]{6}").unwrap();
+        assert!(r.is_match("AB123456"));
+        assert!(!r.is_match("AB12345"));
+        assert!(!r.is_match("aB123456"));
+        let r = Regex::new(r"(ab|a)*c?\.\/").unwrap();
+        for s in ["./", "abac./", "aaab./"] {
+            assert!(r.is_match(s), "{}", s);
+        }
+        assert!(!r.is_match("b./"));
+        let r = Regex::new(r"[^a-y]x{2,}").unwrap();
+        assert!(r.is_match("zxxxx") && !r.is_match("axx") && !r.is_match("zx"));
+        for bad in ["(a", "a{3,1}", "[b-a]", "*a", r"\q", "[^ -~]"] {
+            assert!(Regex::new(bad).is_err(), "{}", bad);
+        }
+        // too many automaton states
+        assert!(Regex::new("a{999}").is_ok());
+        for large in [
+            "a{2000}",
+            "a{20000}",
+            "(a|b)*a(a|b){16}",
+            "((a{99}){99}){99}",
+        ] {
+            assert!(Regex::new(large).is_err(), "{}", large);
+        }
+    }
+
+    #[test]
+    fn generated_and_recognized() {
+        use crate::collection::Collection;
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+        let bnf = r#"<id>::=/[A-Z]{2}[0-9]{6}/|"id-"/\d+(-[a-z]?)*/"#;
+        assert_eq!(
+            crate::parser::parse(bnf).unwrap().bnf(),
+            r#"[BNF] <id> ::= /[A-Z]{2}[0-9]{6}/ | "id-" /\d+(-[a-z]?)*/ "#
+        );
+        let mut c = Collection::new();
+        c.add(bnf).unwrap();
+        let mut rng = StdRng::seed_from_u64(5);
+        for _ in 0..50 {
+            let s = c.gen_with_rng("<id>", &mut rng).unwrap();
+            assert!(c.accepts("<id>", &s).unwrap(), "{}", s);
+            assert!(s.len() <= 3 + 1 + MAX_REPEAT + 2 * MAX_REPEAT, "{}", s);
+        }
+        assert!(c.accepts("<id>", "id-123-a--b").unwrap());
+        assert!(!c.accepts("<id>", "AB12345").unwrap());
+    }
+}
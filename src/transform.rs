@@ -0,0 +1,175 @@
+// transformers, written @name(<expr>) in mBNF
+//
+//   @base64(...)        base64 with padding
+//   @urlencode(...)     percent-encoding of all but A-Z a-z 0-9 - _ . ~
+//   @json_escape(...)   the body of a JSON string literal
+//   @hex(...)           two lowercase hex digits per byte
+//   @upper(...)         upper case
+//   @lower(...)         lower case
+//
+// The text of the expr is generated first and then transformed. The byte-wise ones
+// encode the text as it is written: as UTF-8, or in binary output with a char below
+// U+0100 as one byte. Everything
+// that works on the rules sees the shape of the output instead, a regex accepting
+// every transformed text (over the alphabet of the grammar for the last three),
+// while gen tunes its branch probabilities as if the expr were not transformed.
+use crate::codec::{base64_encode, hex_encode, json_escape, to_bytes, url_encode};
+use crate::regex::{self, Regex};
+use std::collections::BTreeSet;
+
+enum Kind {
+    Base64,
+    UrlEncode,
+    JsonEscape,
+    Hex,
+    Upper,
+    Lower,
+}
+
+pub struct Transform {
+    pub name: String,
+    kind: Kind,
+}
+
+// every char a text of the grammar can hold: printable ascii, whitespace, the chars
+// of its terminals, and their upper and lower case
+pub fn alphabet<'a>(terminals: impl Iterator<Item = &'a str>) -> BTreeSet<char> {
+    let mut chars: BTreeSet<char> = (' '..='~').chain(['\t', '\n', '\r']).collect();
+    terminals.for_each(|t| chars.extend(t.chars()));
+    let mut todo: Vec<char> = chars.iter().copied().collect();
+    while let Some(c) = todo.pop() {
+        // a final sigma lowers to ς
+        let sigma = (c == 'Σ').then_some('ς');
+        for cased in c.to_uppercase().chain(c.to_lowercase()).chain(sigma) {
+            if chars.insert(cased) {
+                todo.push(cased);
+            }
+        }
+    }
+    chars
+}
+
+impl Transform {
+    pub fn new(name: &str) -> Result<Self, String> {
+        let kind = match name {
+            "base64" => Kind::Base64,
+            "urlencode" => Kind::UrlEncode,
+            "json_escape" => Kind::JsonEscape,
+            "hex" => Kind::Hex,
+            "upper" => Kind::Upper,
+            "lower" => Kind::Lower,
+            _ => return Err(format!("unknown transformer @{}", name)),
+        };
+        Ok(Transform {
+            name: name.to_string(),
+            kind,
+        })
+    }
+
+    // the output of the transformer over texts of the given alphabet
+    pub fn shape(&self, alphabet: &BTreeSet<char>) -> Regex {
+        let class = |chars: BTreeSet<char>| {
+            format!(
+                "[{}]",
+                regex::escape(&chars.into_iter().collect::<String>())
+            )
+        };
+        let each = alphabet.iter().copied();
+        let pattern = match self.kind {
+            Kind::Base64 => {
+                "([A-Za-z0-9+/]{4})*([A-Za-z0-9+/]{2}==|[A-Za-z0-9+/]{3}=)?".to_string()
+            }
+            Kind::UrlEncode => r"([A-Za-z0-9\-_.~]|%[0-9A-F]{2})*".to_string(),
+            Kind::JsonEscape => format!(
+                r#"({}|\\["\\nrt]|\\u[0-9a-f]{{4}})*"#,
+                class(
+                    each.filter(|&c| c >= ' ' && c != '"' && c != '\\')
+                        .collect()
+                )
+            ),
+            Kind::Hex => "([0-9a-f]{2})*".to_string(),
+            Kind::Upper => format!("{}*", class(each.flat_map(char::to_uppercase).collect())),
+            Kind::Lower => {
+                let sigma = alphabet.contains(&'Σ').then_some('ς');
+                let lower = each.flat_map(char::to_lowercase).chain(sigma);
+                format!("{}*", class(lower.collect()))
+            }
+        };
+        Regex::new(&pattern).unwrap()
+    }
+
+    pub fn apply(&self, s: &str, binary: bool) -> String {
+        let bytes = || match binary {
+            true => to_bytes(s),
+            false => s.as_bytes().to_vec(),
+        };
+        match self.kind {
+            Kind::Base64 => base64_encode(&bytes()),
+            Kind::UrlEncode => url_encode(&bytes()),
+            Kind::JsonEscape => json_escape(s),
+            Kind::Hex => hex_encode(&bytes()),
+            Kind::Upper => s.to_uppercase(),
+            Kind::Lower => s.to_lowercase(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::base64_decode;
+    use crate::collection::Collection;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn transformed_in_grammar() {
+        let mut c = Collection::new();
+        let req =
+            r#"<req>::="q "@urlencode(<words>)" b "@base64(<words>" "0x00)" u "@upper(<words>)"#;
+        c.add(req).unwrap();
+        c.add(r#"<words>::=<@pick(dict=words)>" "<words>|<@pick(dict=words)>|"a+b""#)
+            .unwrap();
+        c.add(r#"<json>::="s "@json_escape(/[a-z"\\\n]{0,6}/)" h "@hex(0x89"PNG")"#)
+            .unwrap();
+        assert_eq!(
+            crate::parser::parse(req).unwrap().bnf(),
+            r#"[BNF] <req> ::= "q " @urlencode(<words>) " b " @base64(<words> " " 0x00) " u " @upper(<words>) "#
+        );
+        let mut rng = StdRng::seed_from_u64(4);
+        for _ in 0..20 {
+            let (text, choices) = c.gen_recorded("<req>", &mut rng).unwrap();
+            let (q, rest) = text[2..].split_once(" b ").unwrap();
+            let (b, upper) = rest.split_once(" u ").unwrap();
+            assert!(!q.contains(' ') && !q.contains('+'), "{}", text);
+            let decoded = base64_decode(b).unwrap();
+            assert!(decoded.ends_with(b" \0"), "{}", text);
+            assert_eq!(upper, upper.to_uppercase());
+            assert!(c.accepts("<req>", &text).unwrap());
+            assert_eq!(c.replay("<req>", &choices).unwrap(), text);
+            let json = c.gen_with_rng("<json>", &mut rng).unwrap();
+            let (s, h) = json[2..].rsplit_once(" h ").unwrap();
+            assert!(
+                !s.contains('\n') && !s.replace("\\\"", "").contains('"'),
+                "{}",
+                json
+            );
+            assert_eq!(h, "c289504e47"); // 0x89 is U+0089, two bytes of UTF-8
+            assert!(c.accepts("<json>", &json).unwrap());
+        }
+        // outside printable ascii too
+        c.add("<u>::=@upper(0xe9)|@lower(0xc90xd7)|@json_escape(/[\\x80-\\xff]{2}/)")
+            .unwrap();
+        for _ in 0..20 {
+            let u = c.gen_with_rng("<u>", &mut rng).unwrap();
+            assert!(c.accepts("<u>", &u).unwrap(), "{}", u);
+        }
+        assert!(c.accepts("<u>", "É").unwrap() && c.accepts("<u>", "é×").unwrap());
+        // text is encoded as UTF-8, binary output byte per char
+        c.add(r#"<e>::=@urlencode(0xe9)"/"@hex(0xe90x80)"#).unwrap();
+        assert_eq!(c.gen_with_rng("<e>", &mut rng).unwrap(), "%C3%A9/c3a9c280");
+        c.set_binary(true);
+        assert_eq!(c.gen_with_rng("<e>", &mut rng).unwrap(), "%E9/e980");
+        assert!(c.add("<x>::=@rot13(<words>)").is_err());
+        assert!(c.add("<x>::=@upper(<words>").is_err());
+    }
+}
@@ -0,0 +1,141 @@
+// word lists for <@pick(...)>
+//
+// One entry per line; a second column after a tab or comma gives its weight,
+// entries without one weigh 1. Empty lines and lines starting with # are skipped.
+// A few dictionaries are compiled in so grammars work without external files.
+use rand::{Rng, RngCore};
+use std::collections::HashMap;
+use std::fs;
+
+pub const BUNDLED: [(&str, &str); 5] = [
+    ("first_names", include_str!("dict/first_names.txt")),
+    ("surnames", include_str!("dict/surnames.txt")),
+    ("cities", include_str!("dict/cities.txt")),
+    ("countries", include_str!("dict/countries.txt")),
+    ("words", include_str!("dict/words.txt")),
+];
+
+// where a list comes from
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ListSource {
+    File(String),
+    Bundled(String),
+}
+
+// loaded lists, as a Collection caches them
+pub type Lists = HashMap<ListSource, WordList>;
+
+pub struct WordList {
+    pub words: Vec<String>,
+    cumulative: Vec<f64>, // running total of the weights
+}
+
+impl WordList {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (mut words, mut cumulative) = (vec![], vec![]);
+        let mut total = 0.0;
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let weight = |x: &str| {
+                x.trim()
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|x| *x >= 0.0 && x.is_finite())
+            };
+            // a comma column that is no number is part of the entry
+            let (word, weight) = match (line.rsplit_once('\t'), line.rsplit_once(',')) {
+                (Some((w, x)), _) => match weight(x) {
+                    Some(x) => (w, x),
+                    None => return Err(format!("line {}: bad weight {:?}", n + 1, x)),
+                },
+                (None, Some((w, x))) if weight(x).is_some() => (w, weight(x).unwrap()),
+                _ => (line, 1.0),
+            };
+            total += weight;
+            words.push(word.to_string());
+            cumulative.push(total);
+        }
+        if total <= 0.0 {
+            return Err("the list has no entries of positive weight".to_string());
+        }
+        Ok(WordList { words, cumulative })
+    }
+
+    pub fn load(source: &ListSource) -> Result<Self, String> {
+        match source {
+            ListSource::File(path) => {
+                let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+                Self::parse(&text).map_err(|e| format!("{}: {}", path, e))
+            }
+            ListSource::Bundled(name) => {
+                let (_, text) = BUNDLED
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .ok_or_else(|| format!("no bundled dictionary {}", name))?;
+                Self::parse(text)
+            }
+        }
+    }
+
+    pub fn pick(&self, rng: &mut dyn RngCore) -> &str {
+        let total = self.cumulative.last().unwrap();
+        let x = rng.gen::<f64>() * total;
+        let i = self.cumulative.partition_point(|&c| c <= x);
+        &self.words[i.min(self.words.len() - 1)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn weighted_pick() {
+        let list = WordList::parse("# colours\nred\t3\n\nlight blue,0\ngreen\nWashington, D.C.\n")
+            .unwrap();
+        assert_eq!(
+            list.words,
+            ["red", "light blue", "green", "Washington, D.C."]
+        );
+        let mut rng = StdRng::seed_from_u64(4);
+        let reds = (0..1000).filter(|_| list.pick(&mut rng) == "red").count();
+        assert!((500..700).contains(&reds), "{}", reds);
+        assert!((0..1000).all(|_| list.pick(&mut rng) != "light blue"));
+        assert!(WordList::parse("a\tx").is_err());
+        for (name, _) in BUNDLED {
+            let list = WordList::load(&ListSource::Bundled(name.to_string())).unwrap();
+            assert!(list.words.len() > 100, "{}", name);
+        }
+    }
+
+    #[test]
+    fn picked_in_grammar() {
+        use crate::collection::Collection;
+        let path = std::env::temp_dir().join(format!("datarobot-pick-{}.txt", std::process::id()));
+        fs::write(&path, "ann\t1\nbob\t2\n").unwrap();
+        let mut c = Collection::new();
+        let pick = format!(r#"<@pick(file="{}")>"#, path.display());
+        c.add(&format!(r#"<p>::={}" from "<@pick(dict=cities)>"#, pick))
+            .unwrap();
+        // the file is read once, later changes do not show
+        fs::write(&path, "eve\n").unwrap();
+        c.add(&format!("<q>::={}", pick)).unwrap();
+        fs::remove_file(&path).unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..20 {
+            let s = c.gen_with_rng("<p>", &mut rng).unwrap();
+            let (who, city) = s.split_once(" from ").unwrap();
+            assert!(who == "ann" || who == "bob", "{}", s);
+            assert!(BUNDLED[2].1.lines().any(|l| l == city), "{}", s);
+            assert!(c.accepts("<p>", &s).unwrap());
+        }
+        assert_ne!(c.gen_with_rng("<q>", &mut rng).unwrap(), "eve");
+        assert!(!c.accepts("<p>", "eve from Paris").unwrap());
+        assert!(c.add(r#"<r>::=<@pick(file="/no/such/file")>"#).is_err());
+    }
+}